@@ -0,0 +1,428 @@
+// http callbacks for when a job ends, either to the url the client passed in StartJob or to the
+// configured callback url. if a callback secret is set, X-Vertd-Signature is
+// "sha256=" + hex(hmac_sha256(secret, "{X-Vertd-Timestamp}.{body}")) so receivers can check it
+// came from us and isn't a replay. client urls are off unless the operator turns them on, and
+// even then they can only reach public addresses, so they can't be used to poke at the network
+// vertd runs in
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use log::{error, info, warn};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect, Client, StatusCode, Url,
+};
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::config;
+
+const MAX_ATTEMPTS: u32 = 5;
+// doubled after every failed attempt
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CallbackState {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallbackPayload {
+    pub job_id: Uuid,
+    pub state: CallbackState,
+    pub from: String,
+    pub to: String,
+    // size of the converted file in bytes, only there if it completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_size: Option<u64>,
+    // how long the conversion ran for, in seconds
+    pub duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+}
+
+pub fn valid_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+// client urls are checked when the job starts, so a typo fails right away instead of never
+// firing. host names are only resolved when the callback is sent, see PublicResolver
+pub fn valid_client_url(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    // ipv6 hosts keep their brackets
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public(ip),
+        Err(_) => true,
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // carrier-grade nat, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+// resolves like the system does, but drops every address that isn't public. doing it here
+// rather than when the job starts means a host can't resolve to something public for the check
+// and to something internal by the time the callback goes out
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} doesn't resolve to a public address", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+// one client for the configured url, which the operator trusts, and one for client urls, which
+// only reach public addresses and don't follow redirects (those could point anywhere)
+fn http_client(public_only: bool) -> Option<&'static Client> {
+    static TRUSTED: OnceLock<Option<Client>> = OnceLock::new();
+    static PUBLIC: OnceLock<Option<Client>> = OnceLock::new();
+
+    let cell = if public_only { &PUBLIC } else { &TRUSTED };
+    cell.get_or_init(|| {
+        let mut builder = Client::builder().timeout(REQUEST_TIMEOUT);
+        if public_only {
+            builder = builder
+                .dns_resolver(Arc::new(PublicResolver))
+                .redirect(redirect::Policy::none());
+        }
+        match builder.build() {
+            Ok(client) => Some(client),
+            Err(e) => {
+                error!("failed to create http client for callbacks: {}", e);
+                None
+            }
+        }
+    })
+    .as_ref()
+}
+
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// sends the payload to the client's url and the global one (if any) in the background
+pub fn send(client_url: Option<&str>, payload: &CallbackPayload) {
+    let config = &config().callback;
+    // (url, whether it's limited to public addresses)
+    let mut urls: Vec<(String, bool)> = Vec::new();
+    if let Some(url) = &config.url {
+        urls.push((url.expose().to_string(), false));
+    }
+    if let Some(url) = client_url.filter(|_| config.allow_client_urls) {
+        if !urls.iter().any(|(u, _)| u == url) {
+            urls.push((url.to_string(), true));
+        }
+    }
+
+    if urls.is_empty() {
+        return;
+    }
+
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!(
+                "failed to serialize callback for job {}: {}",
+                payload.job_id, e
+            );
+            return;
+        }
+    };
+
+    let secret = config.secret.as_ref().map(|s| s.expose());
+    for (url, public_only) in urls {
+        let Some(client) = http_client(public_only) else {
+            return;
+        };
+        let (job_id, body) = (payload.job_id, body.clone());
+        tokio::spawn(async move {
+            deliver(client, &url, job_id, &body, secret, FIRST_RETRY_DELAY).await;
+        });
+    }
+}
+
+// returns whether the receiver took it
+async fn deliver(
+    client: &Client,
+    url: &str,
+    job_id: Uuid,
+    body: &[u8],
+    secret: Option<&str>,
+    mut delay: Duration,
+) -> bool {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let mut request = client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Vertd-Timestamp", timestamp)
+            .body(body.to_vec());
+        if let Some(secret) = secret {
+            request = request.header(
+                "X-Vertd-Signature",
+                format!("sha256={}", sign(secret, timestamp, body)),
+            );
+        }
+
+        match request.send().await {
+            Ok(res) if res.status().is_success() => {
+                info!("sent callback for job {} to {}", job_id, url);
+                return true;
+            }
+            // the receiver doesn't want it, sending it again won't change that
+            Ok(res)
+                if res.status().is_client_error()
+                    && res.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                warn!(
+                    "callback for job {} was rejected by {} with {}",
+                    job_id,
+                    url,
+                    res.status()
+                );
+                return false;
+            }
+            Ok(res) => warn!(
+                "callback for job {} to {} failed with {} (attempt {}/{})",
+                job_id,
+                url,
+                res.status(),
+                attempt,
+                MAX_ATTEMPTS
+            ),
+            Err(e) => warn!(
+                "callback for job {} to {} failed: {} (attempt {}/{})",
+                job_id, url, e, attempt, MAX_ATTEMPTS
+            ),
+        }
+
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    error!(
+        "giving up on callback for job {} to {} after {} attempts",
+        job_id, url, MAX_ATTEMPTS
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::Mutex,
+    };
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+    struct Received {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    // answers every request with the next status in `statuses`, repeating the last one
+    async fn receiver(statuses: &[u16]) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut statuses = statuses.iter().copied().collect::<VecDeque<_>>();
+
+        let log = Arc::clone(&received);
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut reader = BufReader::new(&mut socket);
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).await.unwrap();
+                log.lock().unwrap().push(Received { headers, body });
+
+                let status = match statuses.len() {
+                    1 => statuses[0],
+                    _ => statuses.pop_front().unwrap(),
+                };
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, received)
+    }
+
+    fn trusted() -> &'static Client {
+        http_client(false).unwrap()
+    }
+
+    #[tokio::test]
+    async fn signature_verifies_with_the_secret() {
+        let (url, received) = receiver(&[200]).await;
+        let body = br#"{"jobId":"x","state":"completed"}"#;
+
+        let delivered = deliver(
+            trusted(),
+            &url,
+            Uuid::nil(),
+            body,
+            Some("hunter2"),
+            RETRY_DELAY,
+        )
+        .await;
+        assert!(delivered);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let request = &received[0];
+        assert_eq!(request.body, body);
+
+        let timestamp = &request.headers["x-vertd-timestamp"];
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"hunter2").unwrap();
+        mac.update(format!("{}.", timestamp).as_bytes());
+        mac.update(body);
+        let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(request.headers["x-vertd-signature"], expected);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_up_to_the_limit() {
+        let (url, received) = receiver(&[503]).await;
+
+        let delivered = deliver(trusted(), &url, Uuid::nil(), b"{}", None, RETRY_DELAY).await;
+        assert!(!delivered);
+        assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn server_errors_stop_being_retried_once_it_works() {
+        let (url, received) = receiver(&[500, 502, 200]).await;
+
+        let delivered = deliver(trusted(), &url, Uuid::nil(), b"{}", None, RETRY_DELAY).await;
+        assert!(delivered);
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (url, received) = receiver(&[404]).await;
+
+        let delivered = deliver(trusted(), &url, Uuid::nil(), b"{}", None, RETRY_DELAY).await;
+        assert!(!delivered);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn client_urls_only_reach_public_addresses() {
+        let (url, received) = receiver(&[200]).await;
+        assert!(!valid_client_url(&url));
+
+        // a name that resolves to loopback gets through the check, but not the resolver
+        let url = url.replace("127.0.0.1", "localhost");
+        assert!(valid_client_url(&url));
+        let public = http_client(true).unwrap();
+        let delivered = deliver(public, &url, Uuid::nil(), b"{}", None, RETRY_DELAY).await;
+        assert!(!delivered);
+        assert!(received.lock().unwrap().is_empty());
+
+        for url in [
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "ftp://example.com/",
+        ] {
+            assert!(!valid_client_url(url), "{}", url);
+        }
+        assert!(valid_client_url("https://example.com/vertd"));
+        assert!(valid_client_url("http://93.184.215.14/"));
+    }
+}
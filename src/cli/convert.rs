@@ -0,0 +1,192 @@
+// vertd convert <input> --to <format>: runs a local file through the same pipeline the server
+// uses, with a progress bar instead of a websocket. the converter only works on the input and
+// output directories, so the file is linked (or copied) in there and the result moved out
+// afterwards
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use indicatif::{ProgressBar, ProgressStyle};
+use tokio::fs;
+
+use crate::{
+    config::config,
+    converter::{
+        format::ConverterFormat,
+        gpu::ConverterGPU,
+        job::{Job, ProgressUpdate},
+        options::ConversionOptions,
+        probe::{detect_format, MediaInfo},
+        speed::ConversionSpeed,
+        Converter,
+    },
+};
+
+// the bar counts tenths of a percent, so slow conversions still visibly move
+const BAR_LENGTH: u64 = 1000;
+
+pub struct ConvertOptions {
+    pub input: PathBuf,
+    pub to: ConverterFormat,
+    pub speed: ConversionSpeed,
+    pub conversion: ConversionOptions,
+    pub output: Option<PathBuf>,
+    pub keep_metadata: bool,
+}
+
+fn progress_bar() -> ProgressBar {
+    let bar = ProgressBar::new(BAR_LENGTH);
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} [{elapsed_precise}] {wide_bar} {msg}")
+            .expect("the template is valid")
+            .progress_chars("=> "),
+    );
+    bar.enable_steady_tick(Duration::from_millis(200));
+    bar
+}
+
+#[derive(Default)]
+struct Stats {
+    percent: f64,
+    frame: Option<u64>,
+    fps: Option<f64>,
+    speed: Option<f64>,
+    eta: Option<f64>,
+}
+
+impl Stats {
+    fn message(&self) -> String {
+        let mut parts = vec![format!("{:.1}%", self.percent)];
+        if let Some(frame) = self.frame {
+            parts.push(format!("frame {}", frame));
+        }
+        if let Some(fps) = self.fps {
+            parts.push(format!("{:.0} fps", fps));
+        }
+        if let Some(speed) = self.speed {
+            parts.push(format!("{:.2}x", speed));
+        }
+        if let Some(eta) = self.eta {
+            parts.push(format!("eta {:.0}s", eta));
+        }
+        parts.join(", ")
+    }
+}
+
+// hard links are free, copies only happen across filesystems
+async fn link_or_copy(from: &Path, to: &str) -> std::io::Result<()> {
+    if fs::hard_link(from, to).await.is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).await.map(|_| ())
+}
+
+async fn move_file(from: &str, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).await?;
+    fs::remove_file(from).await
+}
+
+// returns whether the conversion succeeded
+pub async fn run(options: ConvertOptions, gpu: ConverterGPU) -> anyhow::Result<bool> {
+    // the same detection uploads go through. the conversion needs the probe anyway, so a file
+    // ffprobe can't read is an error here too, whatever its extension says
+    let input = options.input.to_string_lossy().to_string();
+    let extension = options.input.extension().and_then(|ext| ext.to_str());
+    let info = MediaInfo::probe(&input)
+        .await
+        .with_context(|| format!("ffprobe failed to read {}", input))?;
+    let from = detect_format(&input, &info, extension).await?;
+    let output = options
+        .output
+        .unwrap_or_else(|| options.input.with_extension(options.to.to_string()));
+    options
+        .conversion
+        .check(options.to)
+        .map_err(anyhow::Error::msg)?;
+    if output == options.input {
+        anyhow::bail!("the output would overwrite the input, pass --output");
+    }
+
+    let directories = &config().directories;
+    for dir in [&directories.input, &directories.output] {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut job = Job::new(String::new(), from.to_string());
+    job.to = Some(options.to.to_string());
+    let input_path = job.input_path();
+    link_or_copy(&options.input, &input_path)
+        .await
+        .with_context(|| format!("failed to read {}", options.input.display()))?;
+    job.set_media_info(info);
+
+    println!(
+        "converting {} to {} on {}",
+        options.input.display(),
+        options.to,
+        gpu
+    );
+
+    let converter = Converter::new(from, options.to, options.speed, options.keep_metadata)
+        .with_options(options.conversion);
+    let result = converter
+        .convert(&mut job, &gpu, config().gpu.vaapi_device.as_deref())
+        .await;
+    let (mut rx, mut process) = match result {
+        Ok(started) => started,
+        Err(e) => {
+            fs::remove_file(&input_path).await.ok();
+            return Err(e.context("failed to start the conversion"));
+        }
+    };
+
+    let bar = progress_bar();
+    let mut stats = Stats::default();
+    let mut logs = Vec::new();
+    while let Some(update) = rx.recv().await {
+        match update {
+            ProgressUpdate::Percent(percent) => {
+                stats.percent = percent;
+                bar.set_position((percent * BAR_LENGTH as f64 / 100.0) as u64);
+            }
+            ProgressUpdate::Frame(frame) => stats.frame = Some(frame),
+            ProgressUpdate::FPS(fps) => stats.fps = Some(fps),
+            ProgressUpdate::Speed(speed) => stats.speed = Some(speed),
+            ProgressUpdate::Eta(eta) => stats.eta = Some(eta),
+            ProgressUpdate::OutputSize(_) => {}
+            ProgressUpdate::Error(line) => logs.push(line),
+        }
+        bar.set_message(stats.message());
+    }
+    let status = process.wait().await?;
+    bar.finish_and_clear();
+    fs::remove_file(&input_path).await.ok();
+    if let Err(e) = converter.finalize(&job, status.success()).await {
+        logs.push(e.to_string());
+    }
+
+    let output_path = job.output_path().unwrap_or_default();
+    let has_output = fs::metadata(&output_path)
+        .await
+        .is_ok_and(|metadata| metadata.len() > 0);
+
+    if !status.success() || !has_output {
+        fs::remove_file(&output_path).await.ok();
+        for line in logs {
+            eprintln!("{}", line);
+        }
+        eprintln!("conversion failed ({})", status);
+        return Ok(false);
+    }
+
+    move_file(&output_path, &output)
+        .await
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    println!("done, written to {}", output.display());
+    Ok(true)
+}
@@ -0,0 +1,133 @@
+// command line interface. without a subcommand vertd runs the http server like it always did
+
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use uuid::Uuid;
+
+use crate::{
+    config::RetentionMode,
+    converter::{
+        format::ConverterFormat, gpu::ConverterGPU, options::ConversionOptions,
+        speed::ConversionSpeed,
+    },
+};
+
+pub mod convert;
+pub mod replay;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Config file to read, defaults to vertd.toml if there is one
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Use this GPU vendor instead of detecting it (amd, intel, nvidia, apple or cpu)
+    #[arg(long, global = true)]
+    pub gpu: Option<ConverterGPU>,
+
+    /// VA-API device used for AMD and Intel GPUs on Linux
+    #[arg(long, global = true)]
+    pub vaapi_device: Option<String>,
+
+    /// Directory for uploads waiting to be converted
+    #[arg(long, global = true)]
+    pub input_dir: Option<String>,
+
+    /// Directory for converted files waiting to be downloaded
+    #[arg(long, global = true)]
+    pub output_dir: Option<String>,
+
+    /// Directory for files kept after a failed conversion
+    #[arg(long, global = true)]
+    pub permanent_dir: Option<String>,
+
+    /// Address the http server listens on
+    #[arg(long)]
+    pub bind_address: Option<IpAddr>,
+
+    /// Port the http server listens on
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// How long uploads wait for a conversion, in seconds
+    #[arg(long)]
+    pub input_lifetime: Option<u64>,
+
+    /// How long converted files wait for a download, in seconds
+    #[arg(long)]
+    pub output_lifetime: Option<u64>,
+
+    /// When converted files are removed (first-download, downloads or ttl)
+    #[arg(long)]
+    pub retention: Option<RetentionMode>,
+
+    /// How often a converted file can be downloaded with --retention downloads
+    #[arg(long)]
+    pub max_downloads: Option<u32>,
+
+    /// Maximum size of a single upload, in bytes
+    #[arg(long)]
+    pub max_upload_size: Option<u64>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+fn parse_format(s: &str) -> Result<ConverterFormat, String> {
+    s.to_lowercase()
+        .parse()
+        .map_err(|_| format!("unsupported format {}", s))
+}
+
+// same names as the websocket api uses, e.g. ultraFast or slow
+fn parse_speed(s: &str) -> Result<ConversionSpeed, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string()))
+        .map_err(|_| "valid options: ultraFast, fast, medium, slow, slower, verySlow".to_string())
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Convert a local file without the http server
+    Convert {
+        /// File to convert
+        input: PathBuf,
+
+        /// Format to convert to, e.g. mp4, webm, gif or zip for single frames
+        #[arg(long, value_parser = parse_format)]
+        to: ConverterFormat,
+
+        /// How long to spend on quality (ultraFast, fast, medium, slow, slower or verySlow)
+        #[arg(long, default_value = "medium", value_parser = parse_speed)]
+        speed: ConversionSpeed,
+
+        #[command(flatten)]
+        options: Box<ConversionOptions>,
+
+        /// Where to write the result, defaults to the input with the new extension
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+
+        /// Don't copy metadata and chapters over
+        #[arg(long)]
+        strip_metadata: bool,
+    },
+
+    /// Run the conversion a kept file failed on again, locally and without the http server
+    Replay {
+        /// Id of the kept file, i.e. its name in the permanent directory without the extension
+        id: Uuid,
+    },
+}
+
+impl Cli {
+    // -gpu and -vaapi-device were around before there was a proper parser, so they keep working
+    pub fn parse_args() -> Self {
+        Self::parse_from(std::env::args().map(|arg| match arg.as_str() {
+            "-gpu" => "--gpu".to_string(),
+            "-vaapi-device" => "--vaapi-device".to_string(),
+            _ => arg,
+        }))
+    }
+}
@@ -0,0 +1,107 @@
+// vertd replay <id>: runs the conversion a kept file failed on again, with the settings its sidecar
+// recorded. the input is copied to the input directory under a new id, so the kept file itself is
+// never touched
+
+use anyhow::{anyhow, Context};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::config,
+    converter::{
+        format::ConverterFormat,
+        gpu::ConverterGPU,
+        job::{Job, ProgressUpdate},
+        Converter,
+    },
+    kept,
+};
+
+// returns whether the conversion succeeded this time
+pub async fn run(id: Uuid, gpu: Option<ConverterGPU>) -> anyhow::Result<bool> {
+    let directories = &config().directories;
+    let file = kept::find(id)
+        .await
+        .with_context(|| format!("failed to read {}/", directories.permanent))?
+        .ok_or_else(|| anyhow!("no kept file with id {}", id))?;
+    let conversion = file
+        .conversion
+        .ok_or_else(|| anyhow!("{} has no sidecar describing its conversion", file.path))?;
+
+    let from = file
+        .format
+        .parse::<ConverterFormat>()
+        .map_err(|_| anyhow!("unsupported input format {}", file.format))?;
+    let to = conversion
+        .to
+        .parse::<ConverterFormat>()
+        .map_err(|_| anyhow!("unsupported output format {}", conversion.to))?;
+    // the gpu it failed on, unless told otherwise
+    let gpu = match gpu {
+        Some(gpu) => gpu,
+        None => conversion.gpu.parse()?,
+    };
+
+    println!(
+        "replaying {} -> {} ({:?}, {}) on {}",
+        file.path, conversion.to, conversion.speed, conversion.gpu, gpu
+    );
+    if let Some(command) = &conversion.ffmpeg_command {
+        println!("originally ran: {}", command.join(" "));
+    }
+
+    for dir in [&directories.input, &directories.output] {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut job = Job::new(String::new(), file.format.clone());
+    job.filename = file.filename;
+    job.to = Some(conversion.to.clone());
+    let input_path = job.input_path();
+    fs::copy(&file.path, &input_path).await?;
+
+    let converter = Converter::new(from, to, conversion.speed, conversion.keep_metadata)
+        .with_options(conversion.options);
+    let result = converter
+        .convert(&mut job, &gpu, config().gpu.vaapi_device.as_deref())
+        .await;
+    let (mut rx, mut process) = match result {
+        Ok(started) => started,
+        Err(e) => {
+            fs::remove_file(&input_path).await.ok();
+            return Err(e.context("failed to start the conversion"));
+        }
+    };
+
+    if let Some(command) = job
+        .conversion
+        .as_ref()
+        .and_then(|c| c.ffmpeg_command.as_ref())
+    {
+        println!("running: {}", command.join(" "));
+    }
+
+    while let Some(update) = rx.recv().await {
+        if let ProgressUpdate::Error(line) = update {
+            eprintln!("{}", line);
+        }
+    }
+    let status = process.wait().await?;
+    fs::remove_file(&input_path).await.ok();
+    if let Err(e) = converter.finalize(&job, status.success()).await {
+        eprintln!("{}", e);
+    }
+
+    let output_path = job.output_path().unwrap_or_default();
+    let has_output = fs::metadata(&output_path)
+        .await
+        .is_ok_and(|metadata| metadata.len() > 0);
+
+    if status.success() && has_output {
+        println!("conversion succeeded, output is in {}", output_path);
+        Ok(true)
+    } else {
+        fs::remove_file(&output_path).await.ok();
+        eprintln!("conversion failed ({})", status);
+        Ok(false)
+    }
+}
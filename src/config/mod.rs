@@ -0,0 +1,731 @@
+// everything vertd can be configured with, in one place. values come from command line flags,
+// environment variables and a toml file (vertd.toml, or whatever --config / VERTD_CONFIG points
+// to), in that order of precedence. anything that's set but invalid stops vertd from starting,
+// it's better to find out about a typo right away than after the disk filled up
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{callback, cli::Cli, converter::gpu::ConverterGPU};
+
+// read if it exists, --config and VERTD_CONFIG have to point at a file that does
+const DEFAULT_CONFIG_FILE: &str = "vertd.toml";
+
+const GPUS: [ConverterGPU; 5] = [
+    ConverterGPU::NVIDIA,
+    ConverterGPU::AMD,
+    ConverterGPU::Intel,
+    ConverterGPU::Apple,
+    ConverterGPU::CPU,
+];
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// only valid once main() has called load()
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("config is loaded on startup")
+}
+
+// loads the config and makes it available through config(), returns the file it was read from
+pub fn load(cli: &Cli) -> anyhow::Result<Option<PathBuf>> {
+    let (config, file) = Config::load(cli)?;
+    if CONFIG.set(config).is_err() {
+        bail!("config was already loaded");
+    }
+    Ok(file)
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub directories: Directories,
+    pub jobs: JobsConfig,
+    pub limits: Limits,
+    pub gpu: GpuConfig,
+    pub callback: CallbackConfig,
+    pub notify: NotifyConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    // used to link to kept files in notifications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<PrivateUrl>,
+    // the admin api is disabled without one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_password: Option<Secret>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 24153,
+            public_url: None,
+            admin_password: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Directories {
+    // uploads waiting to be converted
+    pub input: String,
+    // converted files waiting to be downloaded
+    pub output: String,
+    // files kept after a failed conversion
+    pub permanent: String,
+}
+
+impl Default for Directories {
+    fn default() -> Self {
+        Self {
+            input: "input".to_string(),
+            output: "output".to_string(),
+            permanent: "permanent".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    // everything is wiped on restart
+    Memory,
+    // jobs and their files survive restarts
+    Sled,
+}
+
+impl FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "memory" => Ok(StoreKind::Memory),
+            "sled" => Ok(StoreKind::Sled),
+            _ => Err("valid options: memory, sled".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetentionMode {
+    // the output goes away once it has been downloaded
+    FirstDownload,
+    // the output can be downloaded max_downloads times
+    Downloads,
+    // the output stays until its lifetime runs out, no matter how often it's downloaded
+    Ttl,
+}
+
+impl FromStr for RetentionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-download" => Ok(RetentionMode::FirstDownload),
+            "downloads" => Ok(RetentionMode::Downloads),
+            "ttl" => Ok(RetentionMode::Ttl),
+            _ => Err("valid options: first-download, downloads, ttl".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    pub store: StoreKind,
+    // only used by the sled store
+    pub store_path: String,
+    // how long an upload is kept around waiting for a conversion
+    #[serde(with = "seconds")]
+    pub input_lifetime: Duration,
+    // how long a converted file is kept around waiting to be downloaded
+    #[serde(with = "seconds")]
+    pub output_lifetime: Duration,
+    pub retention: RetentionMode,
+    // only used with retention = "downloads"
+    pub max_downloads: u32,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            store: StoreKind::Memory,
+            store_path: "jobs.db".to_string(),
+            input_lifetime: Duration::from_secs(60 * 60),
+            output_lifetime: Duration::from_secs(60 * 60),
+            retention: RetentionMode::FirstDownload,
+            max_downloads: 3,
+        }
+    }
+}
+
+impl JobsConfig {
+    // None means downloads are only limited by the output's lifetime
+    pub fn max_downloads(&self) -> Option<u32> {
+        match self.retention {
+            RetentionMode::FirstDownload => Some(1),
+            RetentionMode::Downloads => Some(self.max_downloads),
+            RetentionMode::Ttl => None,
+        }
+    }
+}
+
+// written as e.g. 3840x2160
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once('x').and_then(|(w, h)| {
+            Some(Resolution {
+                width: w.trim().parse().ok()?,
+                height: h.trim().parse().ok()?,
+            })
+        });
+        match parsed {
+            Some(resolution) if resolution.width > 0 && resolution.height > 0 => Ok(resolution),
+            _ => Err("expected a resolution like 3840x2160".to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for Resolution {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Resolution> for String {
+    fn from(resolution: Resolution) -> Self {
+        format!("{}x{}", resolution.width, resolution.height)
+    }
+}
+
+// limits for one of the data directories, unlimited if unset
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskLimits {
+    // in bytes, the oldest files go first once the directory is bigger than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    // files older than this are removed regardless
+    #[serde(with = "optional_seconds", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // in bytes
+    pub max_upload_size: u64,
+    // in seconds, unlimited if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<u64>,
+    // checked regardless of orientation
+    pub max_resolution: Resolution,
+    pub input: DiskLimits,
+    pub output: DiskLimits,
+    pub permanent: DiskLimits,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_upload_size: 10 * 1024 * 1024 * 1024,
+            max_duration: None,
+            max_resolution: Resolution {
+                width: 8192,
+                height: 8192,
+            },
+            input: DiskLimits::default(),
+            output: DiskLimits::default(),
+            permanent: DiskLimits::default(),
+        }
+    }
+}
+
+// how many conversions may run at once on each kind of GPU, the rest wait in a queue
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaxConcurrent {
+    pub nvidia: usize,
+    pub amd: usize,
+    pub intel: usize,
+    pub apple: usize,
+    pub cpu: usize,
+}
+
+impl Default for MaxConcurrent {
+    fn default() -> Self {
+        Self {
+            // consumer cards only allow a handful of NVENC sessions at once
+            nvidia: 3,
+            amd: 2,
+            intel: 2,
+            apple: 2,
+            // a single software encode already uses every core
+            cpu: 1,
+        }
+    }
+}
+
+impl MaxConcurrent {
+    pub fn get(&self, gpu: ConverterGPU) -> usize {
+        match gpu {
+            ConverterGPU::NVIDIA => self.nvidia,
+            ConverterGPU::AMD => self.amd,
+            ConverterGPU::Intel => self.intel,
+            ConverterGPU::Apple => self.apple,
+            ConverterGPU::CPU => self.cpu,
+        }
+    }
+
+    fn get_mut(&mut self, gpu: ConverterGPU) -> &mut usize {
+        match gpu {
+            ConverterGPU::NVIDIA => &mut self.nvidia,
+            ConverterGPU::AMD => &mut self.amd,
+            ConverterGPU::Intel => &mut self.intel,
+            ConverterGPU::Apple => &mut self.apple,
+            ConverterGPU::CPU => &mut self.cpu,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpuConfig {
+    // skips detection when set
+    #[serde(with = "optional_gpu", skip_serializing_if = "Option::is_none")]
+    pub force: Option<ConverterGPU>,
+    // for AMD and Intel on Linux, /dev/dri/renderD128 if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vaapi_device: Option<String>,
+    pub max_concurrent: MaxConcurrent,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CallbackConfig {
+    // called for every job, on top of any callbackUrl set by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<PrivateUrl>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+    // whether clients may pass their own callbackUrl. those only ever reach public addresses
+    pub allow_client_urls: bool,
+}
+
+// each notifier is enabled by setting its url. urls are secrets, most of them have a token in them
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discord_url: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discord_pings: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack_url: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_url: Option<Secret>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_from: Option<String>,
+    // comma separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_to: Option<String>,
+}
+
+// never shows up in logs, printed configs or debug output
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_string()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("<redacted>")
+    }
+}
+
+// a url that can have credentials or a token in it. only its scheme and host are ever printed
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct PrivateUrl(String);
+
+impl PrivateUrl {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    fn origin(&self) -> String {
+        match reqwest::Url::parse(&self.0) {
+            Ok(url) => match url.host_str() {
+                Some(host) => format!("{}://{}/<redacted>", url.scheme(), host),
+                None => "<redacted>".to_string(),
+            },
+            Err(_) => "<redacted>".to_string(),
+        }
+    }
+}
+
+impl FromStr for PrivateUrl {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(PrivateUrl(s.to_string()))
+    }
+}
+
+impl fmt::Debug for PrivateUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.origin())
+    }
+}
+
+impl Serialize for PrivateUrl {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.origin())
+    }
+}
+
+// durations are whole seconds, same as the environment variables always were
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+mod optional_seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_u64(duration.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+mod optional_gpu {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::converter::gpu::ConverterGPU;
+
+    pub fn serialize<S: Serializer>(
+        gpu: &Option<ConverterGPU>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match gpu {
+            Some(gpu) => serializer.serialize_str(&gpu.to_string().to_lowercase()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<ConverterGPU>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|gpu| gpu.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+// unset and empty are the same thing, .env files tend to have a lot of `KEY=`
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn parse_env<T>(key: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    env(key)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("invalid {} '{}': {}", key, value, e))
+        })
+        .transpose()
+}
+
+// for settings which can be disabled, where 0 has always meant unlimited
+fn parse_env_limit(key: &str) -> anyhow::Result<Option<Option<u64>>> {
+    Ok(parse_env::<u64>(key)?.map(|n| Some(n).filter(|n| *n > 0)))
+}
+
+fn set<T>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+fn set_some<T>(field: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *field = value;
+    }
+}
+
+impl Config {
+    fn load(cli: &Cli) -> anyhow::Result<(Self, Option<PathBuf>)> {
+        let file = match cli
+            .config
+            .clone()
+            .or_else(|| env("VERTD_CONFIG").map(PathBuf::from))
+        {
+            Some(path) => Some(path),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        };
+
+        let mut config = match &file {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok((config, file))
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    // the variable names predate the config file, so they aren't all VERTD_ prefixed
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        let server = &mut self.server;
+        set(&mut server.bind_address, parse_env("VERTD_BIND_ADDRESS")?);
+        set(&mut server.port, parse_env("PORT")?);
+        set_some(&mut server.public_url, parse_env("PUBLIC_URL")?);
+        set_some(&mut server.admin_password, parse_env("ADMIN_PASSWORD")?);
+
+        let directories = &mut self.directories;
+        set(&mut directories.input, env("VERTD_INPUT_DIR"));
+        set(&mut directories.output, env("VERTD_OUTPUT_DIR"));
+        set(&mut directories.permanent, env("VERTD_PERMANENT_DIR"));
+
+        let jobs = &mut self.jobs;
+        set(&mut jobs.store, parse_env("VERTD_JOB_STORE")?);
+        set(&mut jobs.store_path, env("VERTD_JOB_STORE_PATH"));
+        set(
+            &mut jobs.input_lifetime,
+            parse_env("VERTD_INPUT_LIFETIME")?.map(Duration::from_secs),
+        );
+        set(
+            &mut jobs.output_lifetime,
+            parse_env("VERTD_OUTPUT_LIFETIME")?.map(Duration::from_secs),
+        );
+        set(&mut jobs.retention, parse_env("VERTD_RETENTION")?);
+        set(&mut jobs.max_downloads, parse_env("VERTD_MAX_DOWNLOADS")?);
+
+        let limits = &mut self.limits;
+        set(
+            &mut limits.max_upload_size,
+            parse_env("VERTD_MAX_UPLOAD_SIZE")?,
+        );
+        set_some(&mut limits.max_duration, parse_env("VERTD_MAX_DURATION")?);
+        set(
+            &mut limits.max_resolution,
+            parse_env("VERTD_MAX_RESOLUTION")?,
+        );
+        for (dir, disk) in [
+            ("INPUT", &mut limits.input),
+            ("OUTPUT", &mut limits.output),
+            ("PERMANENT", &mut limits.permanent),
+        ] {
+            set(
+                &mut disk.max_size,
+                parse_env_limit(&format!("VERTD_{}_MAX_SIZE", dir))?,
+            );
+            set(
+                &mut disk.max_age,
+                parse_env_limit(&format!("VERTD_{}_MAX_AGE", dir))?
+                    .map(|age| age.map(Duration::from_secs)),
+            );
+        }
+
+        let gpu = &mut self.gpu;
+        set_some(&mut gpu.force, parse_env("VERTD_FORCE_GPU")?);
+        set_some(&mut gpu.vaapi_device, env("VERTD_VAAPI_DEVICE_PATH"));
+        for kind in GPUS {
+            let key = format!("VERTD_MAX_CONCURRENT_{}", kind.to_string().to_uppercase());
+            set(gpu.max_concurrent.get_mut(kind), parse_env(&key)?);
+        }
+
+        set_some(&mut self.callback.url, parse_env("VERTD_CALLBACK_URL")?);
+        set_some(
+            &mut self.callback.secret,
+            parse_env("VERTD_CALLBACK_SECRET")?,
+        );
+        set(
+            &mut self.callback.allow_client_urls,
+            parse_env("VERTD_CALLBACK_ALLOW_CLIENT_URLS")?,
+        );
+
+        let notify = &mut self.notify;
+        set_some(&mut notify.discord_url, parse_env("WEBHOOK_URL")?);
+        set_some(&mut notify.discord_pings, env("WEBHOOK_PINGS"));
+        set_some(
+            &mut notify.webhook_url,
+            parse_env("VERTD_NOTIFY_WEBHOOK_URL")?,
+        );
+        set_some(&mut notify.slack_url, parse_env("VERTD_NOTIFY_SLACK_URL")?);
+        set_some(&mut notify.smtp_url, parse_env("VERTD_SMTP_URL")?);
+        set_some(&mut notify.smtp_from, env("VERTD_SMTP_FROM"));
+        set_some(&mut notify.smtp_to, env("VERTD_SMTP_TO"));
+
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        set(&mut self.server.bind_address, cli.bind_address);
+        set(&mut self.server.port, cli.port);
+        set(&mut self.directories.input, cli.input_dir.clone());
+        set(&mut self.directories.output, cli.output_dir.clone());
+        set(&mut self.directories.permanent, cli.permanent_dir.clone());
+        set(
+            &mut self.jobs.input_lifetime,
+            cli.input_lifetime.map(Duration::from_secs),
+        );
+        set(
+            &mut self.jobs.output_lifetime,
+            cli.output_lifetime.map(Duration::from_secs),
+        );
+        set(&mut self.jobs.retention, cli.retention);
+        set(&mut self.jobs.max_downloads, cli.max_downloads);
+        set(&mut self.limits.max_upload_size, cli.max_upload_size);
+        set_some(&mut self.gpu.force, cli.gpu);
+        set_some(&mut self.gpu.vaapi_device, cli.vaapi_device.clone());
+    }
+
+    // whatever can't be checked while parsing a single value
+    fn validate(&self) -> anyhow::Result<()> {
+        let directories = [
+            &self.directories.input,
+            &self.directories.output,
+            &self.directories.permanent,
+        ];
+        if directories.iter().any(|dir| dir.trim().is_empty()) {
+            bail!("directories can't be empty");
+        }
+        if directories[0] == directories[1]
+            || directories[0] == directories[2]
+            || directories[1] == directories[2]
+        {
+            bail!("input, output and permanent have to be different directories");
+        }
+
+        if let Some(url) = &self.server.public_url {
+            if !callback::valid_url(url.expose()) {
+                bail!("invalid public url, expected an http(s) url");
+            }
+        }
+        if let Some(url) = &self.callback.url {
+            if !callback::valid_url(url.expose()) {
+                bail!("invalid callback url, expected an http(s) url");
+            }
+        }
+
+        if self.jobs.input_lifetime.is_zero() || self.jobs.output_lifetime.is_zero() {
+            bail!("input and output lifetimes have to be at least a second");
+        }
+        if self.jobs.max_downloads == 0 {
+            bail!("max downloads has to be at least 1");
+        }
+        if self.limits.max_upload_size == 0 {
+            bail!("max upload size has to be at least a byte");
+        }
+
+        for disk in [
+            &self.limits.input,
+            &self.limits.output,
+            &self.limits.permanent,
+        ] {
+            if disk.max_size == Some(0) || disk.max_age.is_some_and(|age| age.is_zero()) {
+                bail!("disk limits can't be 0, leave them out for no limit");
+            }
+        }
+
+        let max_concurrent = &self.gpu.max_concurrent;
+        if let Some(gpu) = GPUS.iter().find(|gpu| max_concurrent.get(**gpu) == 0) {
+            bail!("max concurrent conversions on {} has to be at least 1", gpu);
+        }
+
+        Ok(())
+    }
+}
+
+// the effective config as toml, with secrets redacted
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let toml = toml::to_string(self).map_err(|_| fmt::Error)?;
+        f.write_str(toml.trim_end())
+    }
+}
@@ -0,0 +1,172 @@
+// codecs a client can ask for instead of the container's default. only containers that can
+// actually hold a codec accept it, everything else keeps its fixed codecs
+
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use super::format::ConverterFormat;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+impl VideoCodec {
+    // the prefix of the hardware encoders, e.g. hevc_nvenc or av1_vaapi
+    pub fn name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Av1 => "av1",
+            VideoCodec::Vp9 => "vp9",
+        }
+    }
+
+    pub fn cpu_encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::Hevc => "libx265",
+            VideoCodec::Av1 => "libsvtav1",
+            VideoCodec::Vp9 => "libvpx-vp9",
+        }
+    }
+
+    // h264 has a 10-bit profile but next to nothing plays it back
+    pub fn supports_10bit(&self) -> bool {
+        !matches!(self, VideoCodec::H264)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum AudioCodec {
+    Aac,
+    Mp3,
+    Opus,
+    Vorbis,
+    Flac,
+}
+
+impl AudioCodec {
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Mp3 => "libmp3lame",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Vorbis => "libvorbis",
+            AudioCodec::Flac => "flac",
+        }
+    }
+}
+
+impl ConverterFormat {
+    pub fn video_codecs(&self) -> &'static [VideoCodec] {
+        match self {
+            ConverterFormat::MP4 | ConverterFormat::M4V | ConverterFormat::MKV => &[
+                VideoCodec::H264,
+                VideoCodec::Hevc,
+                VideoCodec::Av1,
+                VideoCodec::Vp9,
+            ],
+            ConverterFormat::MOV
+            | ConverterFormat::MTS
+            | ConverterFormat::TS
+            | ConverterFormat::M2TS => &[VideoCodec::H264, VideoCodec::Hevc],
+            ConverterFormat::FLV
+            | ConverterFormat::F4V
+            | ConverterFormat::ThreeGP
+            | ConverterFormat::ThreeG2
+            | ConverterFormat::H264 => &[VideoCodec::H264],
+            ConverterFormat::WebM => &[VideoCodec::Av1, VideoCodec::Vp9],
+            _ => &[],
+        }
+    }
+
+    pub fn audio_codecs(&self) -> &'static [AudioCodec] {
+        match self {
+            ConverterFormat::MP4 | ConverterFormat::M4V => {
+                &[AudioCodec::Aac, AudioCodec::Mp3, AudioCodec::Opus]
+            }
+            ConverterFormat::MKV => &[
+                AudioCodec::Aac,
+                AudioCodec::Mp3,
+                AudioCodec::Opus,
+                AudioCodec::Vorbis,
+                AudioCodec::Flac,
+            ],
+            ConverterFormat::MOV
+            | ConverterFormat::MTS
+            | ConverterFormat::TS
+            | ConverterFormat::M2TS
+            | ConverterFormat::FLV
+            | ConverterFormat::F4V => &[AudioCodec::Aac, AudioCodec::Mp3],
+            ConverterFormat::ThreeGP | ConverterFormat::ThreeG2 => &[AudioCodec::Aac],
+            ConverterFormat::WebM => &[AudioCodec::Opus, AudioCodec::Vorbis],
+            // audio formats are their codec, so asking for it again is fine
+            ConverterFormat::MP3 => &[AudioCodec::Mp3],
+            ConverterFormat::AAC => &[AudioCodec::Aac],
+            ConverterFormat::FLAC => &[AudioCodec::Flac],
+            ConverterFormat::Opus => &[AudioCodec::Opus],
+            ConverterFormat::OGG => &[AudioCodec::Vorbis],
+            _ => &[],
+        }
+    }
+}
+
+// the error is meant for the client, so it lists what would have worked
+pub fn check(
+    to: ConverterFormat,
+    video: Option<VideoCodec>,
+    audio: Option<AudioCodec>,
+) -> Result<(), String> {
+    if let Some(codec) = video {
+        let supported = to.video_codecs();
+        if !supported.contains(&codec) {
+            return Err(unsupported("video", codec, to, supported));
+        }
+    }
+    if let Some(codec) = audio {
+        let supported = to.audio_codecs();
+        if !supported.contains(&codec) {
+            return Err(unsupported("audio", codec, to, supported));
+        }
+    }
+    Ok(())
+}
+
+fn unsupported<T: ToString>(kind: &str, codec: T, to: ConverterFormat, supported: &[T]) -> String {
+    if supported.is_empty() {
+        return format!("{} has no {} codec choice", to, kind);
+    }
+    let supported = supported
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "{} can't hold {} {}, supported: {}",
+        to,
+        codec.to_string(),
+        kind,
+        supported
+    )
+}
+
+// keeps 10-bit input 10-bit. vaapi frames stay on the gpu in their own format, so they need nothing
+pub fn ten_bit_args(encoder: &str) -> Vec<String> {
+    if encoder.ends_with("_vaapi") {
+        return Vec::new();
+    }
+    let pix_fmt = if encoder.starts_with("lib") {
+        "yuv420p10le"
+    } else {
+        "p010le"
+    };
+    vec!["-pix_fmt".to_string(), pix_fmt.to_string()]
+}
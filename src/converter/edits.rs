@@ -0,0 +1,350 @@
+// trimming, cropping, scaling, rotating and frame rate changes a client can ask for. they all end
+// up in the one filter chain a conversion runs, in front of whatever the format adds itself
+
+use std::str::FromStr;
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 7680;
+
+// in pixels of the input as it's displayed, i.e. after its rotation metadata is applied but
+// before the rotate edit
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// ffmpeg's own crop syntax, width:height:x:y
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(':')
+            .map(|part| part.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid crop {}, expected width:height:x:y", s))?;
+        let [width, height, x, y] = parts[..] else {
+            return Err(format!("invalid crop {}, expected width:height:x:y", s));
+        };
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Edits {
+    /// Start this many seconds into the input
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+
+    /// Stop this many seconds into the input
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+
+    /// Crop to width:height:x:y, in pixels of the input
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<Crop>,
+
+    /// Scale to this width, the height follows the aspect ratio unless it's set too
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Scale to this height, the width follows the aspect ratio unless it's set too
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Scale down so the longest side is at most this long, smaller videos aren't scaled up
+    #[arg(long, conflicts_with_all = ["width", "height"])]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dimension: Option<u32>,
+
+    /// Rotate clockwise by 0, 90, 180 or 270 degrees
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<u32>,
+
+    /// Mirror the video (horizontal or vertical)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flip: Option<Flip>,
+
+    /// Change the frame rate
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fps: Option<u32>,
+}
+
+// where the frames are when they reach the filters, which decides where the scaling happens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HwFrames {
+    // in memory, scaled with the cpu
+    None,
+    // in memory, but going to nvenc, so they're uploaded for scale_cuda
+    Cuda,
+    // vaapi surfaces going to a vaapi encoder, only downloaded for the filters vaapi can't do
+    Vaapi { ten_bit: bool },
+}
+
+impl HwFrames {
+    pub fn for_encoder(encoder: &str, ten_bit: bool) -> Self {
+        if encoder.ends_with("_nvenc") {
+            HwFrames::Cuda
+        } else if encoder.ends_with("_vaapi") {
+            HwFrames::Vaapi { ten_bit }
+        } else {
+            HwFrames::None
+        }
+    }
+}
+
+fn even(n: f64) -> u32 {
+    ((n / 2.0).round() as u32 * 2).max(2)
+}
+
+impl Edits {
+    // everything that can be checked without the input, the error is meant for the client
+    pub fn check(&self) -> Result<(), String> {
+        let start = self.start.unwrap_or(0.0);
+        if !start.is_finite() || start < 0.0 {
+            return Err("start has to be a positive number of seconds".to_string());
+        }
+        if self.end.is_some_and(|end| !end.is_finite() || end <= start) {
+            return Err("end has to be after start".to_string());
+        }
+        if self
+            .crop
+            .is_some_and(|crop| crop.width < MIN_SIZE || crop.height < MIN_SIZE)
+        {
+            return Err(format!("crop has to be at least {MIN_SIZE}x{MIN_SIZE}"));
+        }
+        let sizes = [self.width, self.height, self.max_dimension];
+        if sizes
+            .iter()
+            .flatten()
+            .any(|size| !(MIN_SIZE..=MAX_SIZE).contains(size))
+        {
+            return Err(format!(
+                "width, height and maxDimension have to be between {MIN_SIZE} and {MAX_SIZE}"
+            ));
+        }
+        if self.max_dimension.is_some() && (self.width.is_some() || self.height.is_some()) {
+            return Err("maxDimension can't be combined with width or height".to_string());
+        }
+        if self.rotate.is_some_and(|r| ![0, 90, 180, 270].contains(&r)) {
+            return Err("rotate has to be 0, 90, 180 or 270".to_string());
+        }
+        if self.fps.is_some_and(|fps| !(1..=240).contains(&fps)) {
+            return Err("fps has to be between 1 and 240".to_string());
+        }
+        Ok(())
+    }
+
+    // the rest, once the input has been probed
+    pub fn check_input(
+        &self,
+        size: Option<(u32, u32)>,
+        duration: Option<f64>,
+    ) -> anyhow::Result<()> {
+        if let (Some(crop), Some((width, height))) = (self.crop, size) {
+            if crop.x + crop.width > width || crop.y + crop.height > height {
+                anyhow::bail!("the crop doesn't fit inside the {}x{} input", width, height);
+            }
+        }
+        if let (Some(start), Some(duration)) = (self.start, duration) {
+            if start >= duration {
+                anyhow::bail!("start is past the end of the {:.1}s input", duration);
+            }
+        }
+        Ok(())
+    }
+
+    // seeking on the input is much faster than throwing away decoded frames
+    pub fn input_args(&self) -> Vec<String> {
+        match self.start {
+            Some(start) => vec!["-ss".to_string(), start.to_string()],
+            None => Vec::new(),
+        }
+    }
+
+    pub fn output_args(&self) -> Vec<String> {
+        match self.end {
+            Some(end) => vec![
+                "-t".to_string(),
+                (end - self.start.unwrap_or(0.0)).to_string(),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    // how long the output is going to be, for the progress
+    pub fn duration(&self, input: Option<f64>) -> Option<f64> {
+        let start = self.start.unwrap_or(0.0);
+        let end = match (self.end, input) {
+            (Some(end), Some(input)) => end.min(input),
+            (end, input) => end.or(input)?,
+        };
+        Some((end - start).max(0.0))
+    }
+
+    fn rotated(&self) -> bool {
+        matches!(self.rotate, Some(90) | Some(270))
+    }
+
+    // crop, rotation and flips, with the frame size after them
+    fn transforms(&self, (width, height): (u32, u32)) -> (Vec<String>, (u32, u32)) {
+        let mut filters = Vec::new();
+        let mut size = (width, height);
+        if let Some(crop) = self.crop {
+            filters.push(format!(
+                "crop={}:{}:{}:{}",
+                crop.width, crop.height, crop.x, crop.y
+            ));
+            size = (crop.width, crop.height);
+        }
+        match self.rotate {
+            Some(90) => filters.push("transpose=clock".to_string()),
+            Some(180) => filters.extend(["hflip".to_string(), "vflip".to_string()]),
+            Some(270) => filters.push("transpose=cclock".to_string()),
+            _ => {}
+        }
+        if self.rotated() {
+            size = (size.1, size.0);
+        }
+        match self.flip {
+            Some(Flip::Horizontal) => filters.push("hflip".to_string()),
+            Some(Flip::Vertical) => filters.push("vflip".to_string()),
+            None => {}
+        }
+        (filters, size)
+    }
+
+    // what to scale to after the transforms, if anything
+    fn scale(&self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let aspect = width as f64 / height as f64;
+        match (self.width, self.height, self.max_dimension) {
+            (Some(w), Some(h), _) => Some((w, h)),
+            (Some(w), None, _) => Some((w, even(w as f64 / aspect))),
+            (None, Some(h), _) => Some((even(h as f64 * aspect), h)),
+            (None, None, Some(max)) if width.max(height) > max => Some(if width >= height {
+                (max, even(max as f64 / aspect))
+            } else {
+                (even(max as f64 * aspect), max)
+            }),
+            _ => None,
+        }
+    }
+
+    // the frame size the encoder gets, before anything the format adds
+    pub fn output_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let (_, size) = self.transforms(size);
+        self.scale(size).unwrap_or(size)
+    }
+
+    // the whole chain. outputs narrower than `min_width` get scaled up to it, which some formats
+    // need. `pix_fmt` is what the encoder wants, it's only used when the frames end up on the gpu
+    // where -pix_fmt can't convert them
+    pub fn filters(
+        &self,
+        input: (u32, u32),
+        min_width: u32,
+        hw: HwFrames,
+        pix_fmt: Option<&str>,
+    ) -> Vec<String> {
+        let (transforms, size) = self.transforms(input);
+        let mut scale = self.scale(size);
+        let (width, height) = scale.unwrap_or(size);
+        if width > 0 && width < min_width {
+            scale = Some((
+                min_width,
+                even(height as f64 * min_width as f64 / width as f64),
+            ));
+        }
+
+        let mut filters = Vec::new();
+        match hw {
+            HwFrames::None => {
+                filters.extend(transforms);
+                if let Some((width, height)) = scale {
+                    filters.push(format!("scale={}:{}", width, height));
+                }
+            }
+            HwFrames::Cuda => {
+                filters.extend(transforms);
+                if let Some((width, height)) = scale {
+                    if let Some(pix_fmt) = pix_fmt {
+                        filters.push(format!("format={}", pix_fmt));
+                    }
+                    filters.push("hwupload_cuda".to_string());
+                    filters.push(format!("scale_cuda={}:{}", width, height));
+                }
+            }
+            HwFrames::Vaapi { ten_bit } => {
+                // vaapi calls 8-bit 4:2:0 nv12. 10-bit input going to an 8-bit encoder has to be
+                // converted on the way, vaapi encoders only take what they can encode
+                let decoded = if ten_bit { "p010le" } else { "nv12" };
+                let encoded = match pix_fmt {
+                    Some("yuv420p") => "nv12",
+                    _ => decoded,
+                };
+                if !transforms.is_empty() {
+                    filters.push("hwdownload".to_string());
+                    filters.push(format!("format={}", decoded));
+                    filters.extend(transforms);
+                    if encoded != decoded {
+                        filters.push(format!("format={}", encoded));
+                    }
+                    filters.push("hwupload".to_string());
+                }
+                match scale {
+                    Some((width, height)) => filters.push(format!(
+                        "scale_vaapi=w={}:h={}:format={}",
+                        width, height, encoded
+                    )),
+                    None if encoded != decoded && filters.is_empty() => {
+                        filters.push(format!("scale_vaapi=format={}", encoded))
+                    }
+                    None => {}
+                }
+            }
+        }
+        if let Some(fps) = self.fps {
+            filters.push(format!("fps={}", fps));
+        }
+        filters
+    }
+}
+
+// whether the chain hands gpu frames to the encoder, in which case -pix_fmt can't be used
+pub fn ends_on_gpu(filters: &[String]) -> bool {
+    filters.iter().any(|f| {
+        f.starts_with("hwupload") || f.starts_with("scale_cuda") || f.starts_with("scale_vaapi")
+    })
+}
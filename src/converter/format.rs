@@ -1,4 +1,11 @@
-use super::{gpu::ConverterGPU, speed::ConversionSpeed};
+use super::{
+    codec::{self, VideoCodec},
+    edits::{self, Edits, HwFrames},
+    gpu::ConverterGPU,
+    options::ConversionOptions,
+    probe::MediaInfo,
+    speed::ConversionSpeed,
+};
 use log::warn;
 use strum_macros::{Display, EnumString};
 
@@ -35,27 +42,155 @@ pub enum ConverterFormat {
     AMV,
     ASF,
     NUT,
+    // audio only, the video is dropped
+    MP3,
+    AAC,
+    FLAC,
+    Opus,
+    WAV,
+    OGG,
+    // animated images, same input rules as gif
+    WebP,
+    APNG,
+    AVIF,
+    // single frames as png or jpeg, see FramesOptions
+    Zip,
 }
 
 impl ConverterFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ConverterFormat::MP4 | ConverterFormat::F4V => "video/mp4",
+            ConverterFormat::WebM => "video/webm",
+            ConverterFormat::GIF => "image/gif",
+            ConverterFormat::AVI | ConverterFormat::DIVX => "video/x-msvideo",
+            ConverterFormat::MKV => "video/x-matroska",
+            ConverterFormat::WMV => "video/x-ms-wmv",
+            ConverterFormat::MOV => "video/quicktime",
+            ConverterFormat::MTS | ConverterFormat::TS | ConverterFormat::M2TS => "video/mp2t",
+            ConverterFormat::MPEG | ConverterFormat::MPG | ConverterFormat::VOB => "video/mpeg",
+            ConverterFormat::FLV => "video/x-flv",
+            ConverterFormat::M4V => "video/x-m4v",
+            ConverterFormat::ThreeGP => "video/3gpp",
+            ConverterFormat::ThreeG2 => "video/3gpp2",
+            ConverterFormat::MXF => "application/mxf",
+            ConverterFormat::OGV => "video/ogg",
+            ConverterFormat::RM => "application/vnd.rn-realmedia",
+            ConverterFormat::RMVB => "application/vnd.rn-realmedia-vbr",
+            ConverterFormat::H264 => "video/h264",
+            ConverterFormat::SWF => "application/x-shockwave-flash",
+            ConverterFormat::ASF => "video/x-ms-asf",
+            ConverterFormat::AMV | ConverterFormat::NUT => "application/octet-stream",
+            ConverterFormat::MP3 => "audio/mpeg",
+            ConverterFormat::AAC => "audio/aac",
+            ConverterFormat::FLAC => "audio/flac",
+            ConverterFormat::Opus => "audio/opus",
+            ConverterFormat::WAV => "audio/wav",
+            ConverterFormat::OGG => "audio/ogg",
+            ConverterFormat::WebP => "image/webp",
+            ConverterFormat::APNG => "image/apng",
+            ConverterFormat::AVIF => "image/avif",
+            ConverterFormat::Zip => "application/zip",
+        }
+    }
+
+    // outputs made of pictures, which have no use for a video bitrate
+    pub fn is_image(&self) -> bool {
+        matches!(
+            self,
+            ConverterFormat::GIF
+                | ConverterFormat::WebP
+                | ConverterFormat::APNG
+                | ConverterFormat::AVIF
+                | ConverterFormat::Zip
+        )
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(
+            self,
+            ConverterFormat::MP3
+                | ConverterFormat::AAC
+                | ConverterFormat::FLAC
+                | ConverterFormat::Opus
+                | ConverterFormat::WAV
+                | ConverterFormat::OGG
+        )
+    }
+
     pub fn conversion_into_args(
         &self,
         speed: &ConversionSpeed,
         gpu: &ConverterGPU,
         bitrate: u64,
+        encoder: Option<&str>,
     ) -> Vec<String> {
-        speed.to_args(self, gpu, bitrate)
+        speed.to_args(self, gpu, bitrate, encoder)
+    }
+}
+
+// drops the video (and cover art, which shows up as a video stream) and encodes the audio only
+fn audio_only(codec: &str) -> Vec<String> {
+    vec!["-vn".to_string(), "-c:a".to_string(), codec.to_string()]
+}
+
+// caps the frame rate and the width, without upscaling. the width stays even for yuv420p
+fn animation_filter(fps: u32, max_width: u32) -> String {
+    format!(
+        "fps={},scale='trunc(min(iw,{})/2)*2':-2:flags=lanczos",
+        fps, max_width
+    )
+}
+
+// puts the edits in front of the format's own filters, so it all stays one graph
+fn prepend_filters(args: &mut Vec<String>, filters: &[String]) {
+    if filters.is_empty() {
+        return;
+    }
+    let chain = filters.join(",");
+    match args
+        .iter()
+        .position(|arg| arg == "-vf" || arg == "-filter_complex")
+    {
+        Some(i) => args[i + 1] = format!("{},{}", chain, args[i + 1]),
+        None => args.extend(["-vf".to_string(), chain]),
     }
 }
 
 pub struct Conversion {
     pub from: ConverterFormat,
     pub to: ConverterFormat,
+    pub options: ConversionOptions,
 }
 
 impl Conversion {
     pub fn new(from: ConverterFormat, to: ConverterFormat) -> Self {
-        Self { from, to }
+        Self {
+            from,
+            to,
+            options: ConversionOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ConversionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // goes before the input, where seeking is fast
+    pub fn input_args(&self) -> Vec<String> {
+        self.options
+            .edits
+            .as_ref()
+            .map(Edits::input_args)
+            .unwrap_or_default()
+    }
+
+    fn audio_encoder(&self, default: &str) -> String {
+        self.options
+            .audio_codec
+            .map_or(default, |codec| codec.encoder())
+            .to_string()
     }
 
     async fn accelerated_or_default_codec(
@@ -78,8 +213,29 @@ impl Conversion {
         gpu: &ConverterGPU,
         bitrate: u64,
         fps: u32,
-        job: &super::job::Job,
+        info: &MediaInfo,
     ) -> anyhow::Result<Vec<String>> {
+        if !self.to.is_audio() && info.video().is_none() {
+            return Err(anyhow::anyhow!(
+                "the input has no video stream, so it can only be converted to an audio format"
+            ));
+        }
+        self.options.check(self.to).map_err(anyhow::Error::msg)?;
+
+        let no_edits = Edits::default();
+        let edits = self.options.edits.as_ref().unwrap_or(&no_edits);
+        let video = info.video();
+        let size = video.map(|v| v.display_size());
+        edits.check_input(size, info.duration)?;
+        let size = size.unwrap_or_default();
+        let ten_bit = video.is_some_and(|v| v.is_10bit());
+        // what comes out of the edits, the format's own limits apply on top
+        let fps = edits.fps.unwrap_or(fps);
+
+        // only set when the client picked the codec, the speed presets then follow the encoder
+        let mut chosen_encoder = None;
+        // set by the formats that build the edit filters themselves, for their encoder
+        let mut video_filters = None;
         let conversion_opts: Vec<String> = match self.to {
             ConverterFormat::MP4
             | ConverterFormat::MKV
@@ -93,38 +249,62 @@ impl Conversion {
             | ConverterFormat::ThreeGP
             | ConverterFormat::ThreeG2
             | ConverterFormat::H264 => {
+                let codec = self.options.video_codec.unwrap_or(VideoCodec::H264);
                 let encoder = self
-                    .accelerated_or_default_codec(gpu, &["h264"], "libx264")
+                    .accelerated_or_default_codec(gpu, &[codec.name()], codec.cpu_encoder())
                     .await;
+                if self.options.video_codec.is_some() {
+                    chosen_encoder = Some(encoder.clone());
+                }
 
                 let mut args = vec!["-c:v".to_string(), encoder.clone()];
 
-                let (width, height) = job.resolution().await?;
+                // the size after the edits, which is what the level has to fit
+                let (width, height) = edits.output_size(size);
                 let is_4k = width >= 3840 || height >= 2160;
-                let pix_fmt = job.pix_fmt().await?;
 
-                // convert to 8bit if 10bit (h264_nvenc does not support 10bit)
-                // could probably use h265 instead?
-                let is_10bit = pix_fmt.contains("10le") || pix_fmt.contains("10be");
-                if is_10bit {
-                    args.extend(["-pix_fmt".to_string(), "yuv420p".to_string()]);
+                // convert to 8bit if 10bit (h264_nvenc does not support 10bit), the newer codecs
+                // keep it
+                let pix_fmt_args = match (ten_bit, codec.supports_10bit()) {
+                    (false, _) => Vec::new(),
+                    (true, true) => codec::ten_bit_args(&encoder),
+                    (true, false) => vec!["-pix_fmt".to_string(), "yuv420p".to_string()],
+                };
+                // scale up to 160 wide if the output would be narrower
+                let filters = edits.filters(
+                    size,
+                    160,
+                    HwFrames::for_encoder(&encoder, ten_bit),
+                    pix_fmt_args.get(1).map(String::as_str),
+                );
+                if !edits::ends_on_gpu(&filters) {
+                    args.extend(pix_fmt_args);
+                }
+                video_filters = Some(filters);
+
+                // apple players only take hevc in mp4 and mov with this tag
+                if codec == VideoCodec::Hevc
+                    && matches!(
+                        self.to,
+                        ConverterFormat::MP4 | ConverterFormat::M4V | ConverterFormat::MOV
+                    )
+                {
+                    args.extend(["-tag:v".to_string(), "hvc1".to_string()]);
                 }
 
                 if is_4k {
-                    args.extend(["-level:v".to_string(), "5.2".to_string()]);
+                    // the level is an h264 thing
+                    if codec == VideoCodec::H264 {
+                        args.extend(["-level:v".to_string(), "5.2".to_string()]);
+                    }
                     if fps > 120 {
                         args.extend(["-r".to_string(), "120".to_string()]);
                     }
                 }
 
-                // scale to 160:-1 if width is less than 160
-                if width < 160 {
-                    args.extend(["-vf".to_string(), "scale=160:-1".to_string()]);
-                }
-
                 args.extend([
                     "-c:a".to_string(),
-                    "aac".to_string(),
+                    self.audio_encoder("aac"),
                     "-strict".to_string(),
                     "experimental".to_string(),
                 ]);
@@ -132,15 +312,49 @@ impl Conversion {
                 args
             }
 
-            ConverterFormat::GIF => {
-                vec![
-                   "-filter_complex".to_string(), 
-                   format!(
-                    "fps={},scale=800:-1:flags=lanczos,split[s0][s1];[s0]palettegen=max_colors=64[p];[s1][p]paletteuse=dither=bayer",
-                    fps.min(24)
-                   )
-                ]
-            }
+            ConverterFormat::GIF => self
+                .options
+                .gif
+                .clone()
+                .unwrap_or_default()
+                .resolve(speed)
+                .to_args(fps),
+
+            // sized for sharing rather than archiving, apng is lossless so it gets the smallest
+            ConverterFormat::WebP => vec![
+                "-vf".to_string(),
+                animation_filter(fps.min(30), 960),
+                "-c:v".to_string(),
+                "libwebp".to_string(),
+                "-loop".to_string(),
+                "0".to_string(),
+                "-an".to_string(),
+            ],
+
+            ConverterFormat::APNG => vec![
+                "-vf".to_string(),
+                animation_filter(fps.min(24), 640),
+                "-c:v".to_string(),
+                "apng".to_string(),
+                "-plays".to_string(),
+                "0".to_string(),
+                "-an".to_string(),
+            ],
+
+            ConverterFormat::AVIF => vec![
+                "-vf".to_string(),
+                animation_filter(fps.min(30), 1280),
+                "-c:v".to_string(),
+                "libsvtav1".to_string(),
+                "-pix_fmt".to_string(),
+                "yuv420p".to_string(),
+                "-loop".to_string(),
+                "0".to_string(),
+                "-an".to_string(),
+            ],
+
+            // checked above, so there's always options by now
+            ConverterFormat::Zip => self.options.frames.clone().unwrap_or_default().to_args(),
 
             ConverterFormat::WMV => {
                 let encoder = self
@@ -155,15 +369,37 @@ impl Conversion {
             }
 
             ConverterFormat::WebM => {
-                let encoder = self
-                    .accelerated_or_default_codec(gpu, &["av1", "vp9", "vp8"], "libvpx")
-                    .await;
-                vec![
-                    "-c:v".to_string(),
-                    encoder.to_string(),
-                    "-c:a".to_string(),
-                    "libvorbis".to_string(),
-                ]
+                let encoder = match self.options.video_codec {
+                    Some(codec) => {
+                        let encoder = self
+                            .accelerated_or_default_codec(gpu, &[codec.name()], codec.cpu_encoder())
+                            .await;
+                        chosen_encoder = Some(encoder.clone());
+                        encoder
+                    }
+                    None => {
+                        self.accelerated_or_default_codec(gpu, &["av1", "vp9", "vp8"], "libvpx")
+                            .await
+                    }
+                };
+                let mut args = vec!["-c:v".to_string(), encoder.clone()];
+                let pix_fmt_args = if self.options.video_codec.is_some() && ten_bit {
+                    codec::ten_bit_args(&encoder)
+                } else {
+                    Vec::new()
+                };
+                let filters = edits.filters(
+                    size,
+                    0,
+                    HwFrames::for_encoder(&encoder, ten_bit),
+                    pix_fmt_args.get(1).map(String::as_str),
+                );
+                if !edits::ends_on_gpu(&filters) {
+                    args.extend(pix_fmt_args);
+                }
+                video_filters = Some(filters);
+                args.extend(["-c:a".to_string(), self.audio_encoder("libvorbis")]);
+                args
             }
 
             ConverterFormat::NUT | ConverterFormat::AVI => vec![
@@ -255,16 +491,39 @@ impl Conversion {
                 "-strict".to_string(),
                 "-1".to_string(),
             ],
+
+            // quality settings for these come from the conversion speed
+            ConverterFormat::MP3 => audio_only("libmp3lame"),
+            ConverterFormat::AAC => audio_only("aac"),
+            ConverterFormat::FLAC => audio_only("flac"),
+            ConverterFormat::Opus => audio_only("libopus"),
+            ConverterFormat::OGG => audio_only("libvorbis"),
+            // 24-bit sources stay 24-bit, everything else is plain cd quality
+            ConverterFormat::WAV => {
+                let deep = info
+                    .audio()
+                    .and_then(|audio| audio.bit_depth)
+                    .is_some_and(|depth| depth > 16);
+                audio_only(if deep { "pcm_s24le" } else { "pcm_s16le" })
+            }
         };
 
-        let conversion_opts = conversion_opts
+        let mut conversion_opts = conversion_opts
             .iter()
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
 
+        if !self.to.is_audio() {
+            let filters =
+                video_filters.unwrap_or_else(|| edits.filters(size, 0, HwFrames::None, None));
+            prepend_filters(&mut conversion_opts, &filters);
+        }
+        conversion_opts.extend(edits.output_args());
+
         let result = [
             conversion_opts,
-            self.to.conversion_into_args(speed, gpu, bitrate),
+            self.to
+                .conversion_into_args(speed, gpu, bitrate, chosen_encoder.as_deref()),
         ]
         .concat();
 
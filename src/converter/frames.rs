@@ -0,0 +1,131 @@
+// the "zip" target: ffmpeg writes single frames as images into a directory next to the output,
+// which gets packed into the zip once it's done
+
+use std::{fs::File, io, path::Path};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use super::format::ConverterFormat;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum FrameImage {
+    #[default]
+    Png,
+    #[strum(serialize = "jpeg", serialize = "jpg")]
+    Jpeg,
+}
+
+impl FrameImage {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FrameImage::Png => "png",
+            FrameImage::Jpeg => "jpg",
+        }
+    }
+}
+
+// either every nth frame or the one frame at a timestamp, e.g. for a thumbnail
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FramesOptions {
+    /// With --to zip, what to export the frames as (png or jpeg)
+    #[arg(long, default_value_t)]
+    #[serde(default)]
+    pub image: FrameImage,
+
+    /// With --to zip, export every nth frame
+    #[arg(long, conflicts_with = "at")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<u32>,
+
+    /// With --to zip, export the one frame this many seconds in
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<f64>,
+}
+
+impl FramesOptions {
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = match (self.every, self.at) {
+            (_, Some(at)) => vec![
+                "-ss".to_string(),
+                at.to_string(),
+                "-frames:v".to_string(),
+                "1".to_string(),
+            ],
+            // without vfr the dropped frames would be filled with duplicates again
+            (every, None) => vec![
+                "-vf".to_string(),
+                format!("select='not(mod(n,{}))'", every.unwrap_or(1)),
+                "-fps_mode".to_string(),
+                "vfr".to_string(),
+            ],
+        };
+        match self.image {
+            FrameImage::Png => args.extend(["-c:v", "png"].map(String::from)),
+            // 2 is near the top of mjpeg's 2-31 scale
+            FrameImage::Jpeg => args.extend(["-c:v", "mjpeg", "-q:v", "2"].map(String::from)),
+        }
+        args.push("-an".to_string());
+        args
+    }
+}
+
+// the error is meant for the client, just like the codec ones
+pub fn check(to: ConverterFormat, frames: Option<&FramesOptions>) -> Result<(), String> {
+    let Some(frames) = frames else {
+        if to == ConverterFormat::Zip {
+            return Err("zip needs frames to say which frames to export".to_string());
+        }
+        return Ok(());
+    };
+    if to != ConverterFormat::Zip {
+        return Err(format!("frames only work with zip, not {}", to));
+    }
+    match (frames.every, frames.at) {
+        (Some(_), Some(_)) | (None, None) => Err("frames needs either every or at".to_string()),
+        (Some(0), None) => Err("every has to be at least 1".to_string()),
+        (None, Some(at)) if !at.is_finite() || at < 0.0 => {
+            Err("at has to be a positive number of seconds".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+// where ffmpeg writes the frames to, the pattern goes inside it
+pub fn frames_dir(output_path: &str) -> String {
+    format!("{}.frames", output_path)
+}
+
+pub fn frame_pattern(dir: &str, image: FrameImage) -> String {
+    format!("{}/%06d.{}", dir, image.extension())
+}
+
+// packs the frames into `zip_path`. images are compressed already, so they're stored as is
+pub async fn pack(dir: String, zip_path: String) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || write_zip(Path::new(&dir), &zip_path)).await?
+}
+
+fn write_zip(dir: &Path, zip_path: &str) -> anyhow::Result<()> {
+    let mut names = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    if names.is_empty() {
+        anyhow::bail!("no frames were exported, the timestamp may be past the end of the input");
+    }
+    names.sort();
+
+    let mut zip = ZipWriter::new(File::create(zip_path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for name in names {
+        zip.start_file(name.to_string_lossy(), options)?;
+        io::copy(&mut File::open(dir.join(&name))?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
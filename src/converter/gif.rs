@@ -0,0 +1,138 @@
+// gif options a client can set on top of what the conversion speed picks
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+use super::{format::ConverterFormat, speed::ConversionSpeed};
+
+// the names are ffmpeg's paletteuse ones
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Dither {
+    None,
+    Bayer,
+    Heckbert,
+    FloydSteinberg,
+    Sierra2,
+    #[serde(rename = "sierra2_4a")]
+    #[strum(serialize = "sierra2_4a")]
+    Sierra24A,
+    Sierra3,
+    Burkes,
+    Atkinson,
+}
+
+// the cli flags are prefixed where the plain name would be ambiguous next to the other options
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GifOptions {
+    /// With --to gif, the widest it gets, defaults depend on --speed like the other gif options
+    #[arg(long = "gif-width")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+
+    /// With --to gif, frames per second, capped at the input's
+    #[arg(id = "gif_fps", long = "gif-fps")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fps: Option<u32>,
+
+    /// With --to gif, palette size from 2 to 256
+    #[arg(long = "gif-colors")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colors: Option<u32>,
+
+    /// With --to gif, dither algorithm, e.g. none, bayer, floyd_steinberg or sierra2_4a
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dither: Option<Dither>,
+
+    /// With --to gif, how often it repeats: 0 forever, -1 plays it once
+    #[arg(long = "gif-loop", allow_negative_numbers = true)]
+    #[serde(default, rename = "loop", skip_serializing_if = "Option::is_none")]
+    pub loop_count: Option<i32>,
+
+    /// With --to gif, a palette per frame for less banding but bigger files
+    #[arg(long, num_args = 0, default_missing_value = "true")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_frame_palette: Option<bool>,
+}
+
+impl GifOptions {
+    // whatever wasn't set comes from the speed
+    pub fn resolve(&self, speed: &ConversionSpeed) -> GifSettings {
+        let defaults = speed.gif_defaults();
+        GifSettings {
+            max_width: self.max_width.unwrap_or(defaults.max_width),
+            fps: self.fps.unwrap_or(defaults.fps),
+            colors: self.colors.unwrap_or(defaults.colors),
+            dither: self.dither.unwrap_or(defaults.dither),
+            loop_count: self.loop_count.unwrap_or(defaults.loop_count),
+            per_frame_palette: self.per_frame_palette.unwrap_or(defaults.per_frame_palette),
+        }
+    }
+}
+
+pub struct GifSettings {
+    pub max_width: u32,
+    pub fps: u32,
+    pub colors: u32,
+    pub dither: Dither,
+    pub loop_count: i32,
+    pub per_frame_palette: bool,
+}
+
+impl GifSettings {
+    pub fn to_args(&self, input_fps: u32) -> Vec<String> {
+        let fps = match input_fps {
+            0 => self.fps,
+            input_fps => self.fps.min(input_fps),
+        };
+        // rectangle diffing only redraws what changed between frames, which is where most of the
+        // size goes. frames with their own palette have nothing to share, so they skip it
+        let (stats_mode, palette_use) = if self.per_frame_palette {
+            ("single", ":new=1")
+        } else {
+            ("full", ":diff_mode=rectangle")
+        };
+        vec![
+            "-filter_complex".to_string(),
+            format!(
+                "fps={},scale='min(iw,{})':-1:flags=lanczos,split[s0][s1];[s0]palettegen=max_colors={}:stats_mode={}[p];[s1][p]paletteuse=dither={}{}",
+                fps, self.max_width, self.colors, stats_mode, self.dither, palette_use
+            ),
+            "-loop".to_string(),
+            self.loop_count.to_string(),
+        ]
+    }
+}
+
+// the error is meant for the client, just like the codec ones
+pub fn check(to: ConverterFormat, gif: Option<&GifOptions>) -> Result<(), String> {
+    let Some(gif) = gif else {
+        return Ok(());
+    };
+    if to != ConverterFormat::GIF {
+        return Err(format!("gif options only work with gif, not {}", to));
+    }
+    if gif
+        .max_width
+        .is_some_and(|width| !(16..=3840).contains(&width))
+    {
+        return Err("maxWidth has to be between 16 and 3840".to_string());
+    }
+    if gif.fps.is_some_and(|fps| !(1..=50).contains(&fps)) {
+        return Err("fps has to be between 1 and 50".to_string());
+    }
+    if gif
+        .colors
+        .is_some_and(|colors| !(2..=256).contains(&colors))
+    {
+        return Err("colors has to be between 2 and 256".to_string());
+    }
+    if gif.loop_count.is_some_and(|count| count < -1) {
+        return Err("loop has to be -1 (once), 0 (forever) or a repeat count".to_string());
+    }
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use anyhow::anyhow;
 use log::warn;
-use std::fmt::{self, Display, Formatter};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
 use tokio::process::Command;
 use wgpu::Instance;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ConverterGPU {
     AMD,
     Intel,
@@ -16,7 +19,9 @@ pub enum ConverterGPU {
 impl ConverterGPU {
     pub async fn get_accelerated_codec(&self, codec: &str) -> anyhow::Result<String> {
         if matches!(self, ConverterGPU::CPU) {
-            return Err(anyhow!("CPU only uses software encoding, not hardware acceleration"));
+            return Err(anyhow!(
+                "CPU only uses software encoding, not hardware acceleration"
+            ));
         }
 
         let priority = self.encoder_priority();
@@ -56,8 +61,10 @@ impl ConverterGPU {
         }
     }
 
+    // `vaapi_frames` keeps decoded frames on the gpu, which only works if a vaapi encoder takes
+    // them. anything else can't read vaapi surfaces, so they're downloaded while decoding instead
     #[allow(unused_variables)]
-    pub fn hwaccel_args(&self, vaapi_device_path: Option<&str>) -> Vec<String> {
+    pub fn hwaccel_args(&self, vaapi_device_path: Option<&str>, vaapi_frames: bool) -> Vec<String> {
         // CPU mode doesn't use hardware acceleration
         if matches!(self, ConverterGPU::CPU) {
             return vec![];
@@ -74,8 +81,10 @@ impl ConverterGPU {
 
                 args.push("-vaapi_device".to_string());
                 args.push(device_path.to_string());
-                args.push("-hwaccel_output_format".to_string());
-                args.push("vaapi".to_string());
+                if vaapi_frames {
+                    args.push("-hwaccel_output_format".to_string());
+                    args.push("vaapi".to_string());
+                }
                 args
             }
             ConverterGPU::CPU => vec![], // should be redundant due to the check at the start of the function
@@ -104,6 +113,24 @@ impl Display for ConverterGPU {
     }
 }
 
+impl FromStr for ConverterGPU {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "amd" => Ok(ConverterGPU::AMD),
+            "intel" => Ok(ConverterGPU::Intel),
+            "nvidia" => Ok(ConverterGPU::NVIDIA),
+            "apple" => Ok(ConverterGPU::Apple),
+            "cpu" => Ok(ConverterGPU::CPU),
+            _ => Err(anyhow!(
+                "{}. Valid options: amd, intel, nvidia, apple, cpu",
+                s
+            )),
+        }
+    }
+}
+
 async fn is_docker() -> bool {
     let dockerenv = tokio::fs::metadata("/.dockerenv").await.is_ok();
     let cgroup = tokio::fs::metadata("/proc/1/cgroup").await.is_ok();
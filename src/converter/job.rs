@@ -1,7 +1,11 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
-use tokio::process::Command;
 use uuid::Uuid;
 
+use super::{options::ConversionOptions, probe::MediaInfo, speed::ConversionSpeed};
+use crate::config::config;
+
 const DEFAULT_BITRATE: u64 = 4 * 1_000_000;
 const BITRATE_MULTIPLIER: f64 = 2.5;
 
@@ -12,10 +16,46 @@ pub struct Job {
     pub auth: String,
     pub from: String,
     pub to: Option<String>,
+    // what the file was called when it was uploaded, used to name the download
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
     pub state: JobState,
+    // unix timestamp (seconds) after which the job and its files get removed
+    #[serde(default)]
+    pub expires_at: u64,
+    // why the last conversion failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // how many times the output was downloaded in full
+    #[serde(default)]
+    pub downloads: u32,
+    // whether the input was moved to permanent/ with /keep
+    #[serde(default)]
+    pub kept: bool,
+    // how the last conversion was run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<ConversionDetails>,
     total_frames: Option<u64>,
     bitrate: Option<u64>,
     fps: Option<u32>,
+    // left out of api responses, the sled store keeps it next to the job instead
+    #[serde(skip)]
+    info: Option<MediaInfo>,
+}
+
+// everything needed to run a conversion again by hand
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionDetails {
+    pub to: String,
+    pub speed: ConversionSpeed,
+    pub keep_metadata: bool,
+    #[serde(flatten)]
+    pub options: ConversionOptions,
+    pub gpu: String,
+    // the exact arguments ffmpeg was started with, missing if it never got that far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ffmpeg_command: Option<Vec<String>>,
 }
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
@@ -25,6 +65,13 @@ pub enum JobState {
     Failed,
 }
 
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 impl Job {
     pub fn new(auth_token: String, from: String) -> Self {
         Self {
@@ -32,13 +79,28 @@ impl Job {
             auth: auth_token,
             from,
             to: None,
+            filename: None,
             state: JobState::Processing,
+            expires_at: unix_now() + config().jobs.input_lifetime.as_secs(),
+            error: None,
+            downloads: 0,
+            kept: false,
+            conversion: None,
             total_frames: None,
             bitrate: None,
             fps: None,
+            info: None,
         }
     }
 
+    pub fn extend_lifetime(&mut self, lifetime: Duration) {
+        self.expires_at = unix_now() + lifetime.as_secs();
+    }
+
+    pub fn expired(&self) -> bool {
+        self.expires_at <= unix_now()
+    }
+
     pub fn completed(&self) -> bool {
         self.state == JobState::Completed
     }
@@ -47,244 +109,110 @@ impl Job {
         self.state == JobState::Failed
     }
 
-    pub fn processing(&self) -> bool {
-        self.state == JobState::Processing
+    pub fn input_path(&self) -> String {
+        format!("{}/{}.{}", config().directories.input, self.id, self.from)
     }
 
-    // TODO: scale based on resolution
-    pub async fn bitrate(&mut self) -> anyhow::Result<u64> {
-        // Ok(DEFAULT_BITRATE)
-        if let Some(bitrate) = self.bitrate {
-            return Ok(bitrate);
-        }
-
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=bit_rate",
-                "-of",
-                "default=nokey=1:noprint_wrappers=1",
-                &format!("input/{}.{}", self.id, self.from),
-            ])
-            .output()
-            .await?;
-
-        let bitrate = String::from_utf8(output.stdout)?;
-        let bitrate = match bitrate.trim().parse::<u64>() {
-            Ok(bitrate) => bitrate,
-            Err(_) => DEFAULT_BITRATE,
-        };
-
-        self.bitrate = Some(bitrate);
-        Ok(((bitrate as f64) * BITRATE_MULTIPLIER) as u64)
+    // where a resumable upload is written to until it's finalized
+    pub fn part_path(&self) -> String {
+        format!("{}/{}.part", config().directories.input, self.id)
     }
 
-    pub async fn total_frames(&mut self) -> anyhow::Result<u64> {
-        if let Some(total_frames) = self.total_frames {
-            return Ok(total_frames);
-        }
-
-        let path = format!("input/{}.{}", self.id, self.from);
-
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-count_packets",
-                "-show_entries",
-                "stream=nb_read_packets",
-                "-of",
-                "csv=p=0",
-                &path,
-            ])
-            .output()
-            .await?;
+    pub fn output_path(&self) -> Option<String> {
+        self.to
+            .as_ref()
+            .map(|to| format!("{}/{}.{}", config().directories.output, self.id, to))
+    }
 
-        let total_frames = String::from_utf8(output.stdout)
-            .map_err(|e| anyhow::anyhow!("failed to parse total frames: {}", e))?
-            .lines()
-            .find_map(|s| {
-                // Filter out non-numeric characters
-                let numeric: String = s.chars().filter(|c| c.is_numeric()).collect();
-                numeric.parse::<u64>().ok()
+    // the original name with the new extension, e.g. "holiday.mov" -> "holiday.mp4"
+    pub fn download_filename(&self) -> Option<String> {
+        let to = self.to.as_ref()?;
+        let name = self
+            .filename
+            .as_deref()
+            .and_then(|name| name.rsplit(['/', '\\']).next())
+            .map(|name| name.rsplit_once('.').map_or(name, |(stem, _)| stem))
+            .map(|stem| {
+                stem.chars()
+                    .filter(|c| !c.is_control() && *c != '"')
+                    .collect::<String>()
             })
-            .ok_or_else(|| anyhow::anyhow!("Error parsing total frames from output"))?;
-
-        self.total_frames = Some(total_frames);
-        Ok(total_frames)
+            .filter(|stem| !stem.trim().is_empty())
+            .unwrap_or_else(|| self.id.to_string());
+        Some(format!("{}.{}", name.trim(), to))
     }
 
-    pub async fn fps(&mut self) -> anyhow::Result<u32> {
-        if let Some(fps) = self.fps {
-            return Ok(fps);
+    // probes the input once and caches the result, everything else is derived from it
+    pub async fn media_info(&mut self) -> anyhow::Result<&MediaInfo> {
+        if self.info.is_none() {
+            let info = MediaInfo::probe(&self.input_path()).await?;
+            self.set_media_info(info);
         }
 
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=r_frame_rate",
-                "-of",
-                "default=nokey=1:noprint_wrappers=1",
-                &format!("input/{}.{}", self.id, self.from),
-            ])
-            .output()
-            .await?;
-
-        // its  gonna look like "30000/1001"
-        let fps = String::from_utf8(output.stdout)
-            .map_err(|e| anyhow::anyhow!("failed to parse fps: {}", e))?;
-
-        let fps = fps.trim().split('/').collect::<Vec<&str>>();
-        let fps = if fps.len() == 1 {
-            fps[0].parse::<u32>()?
-        } else if fps.len() == 2 {
-            let numerator = fps[0].parse::<u32>()?;
-            let denominator = fps[1].parse::<u32>()?;
-            (numerator as f64 / denominator as f64).round() as u32
-        } else if fps.len() == 3 {
-            let numerator = fps[0].parse::<u32>()?;
-            let denominator = fps[2].parse::<u32>()?;
-            (numerator as f64 / denominator as f64).round() as u32
-        } else {
-            return Err(anyhow::anyhow!("failed to parse fps"));
-        };
-
-        self.fps = Some(fps);
-        Ok(fps)
+        self.info
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("failed to probe input"))
     }
 
-    pub async fn bitrate_and_fps(&mut self) -> anyhow::Result<(u64, u32)> {
-        let (bitrate, fps) = (self.bitrate().await?, self.fps().await?);
-        Ok((bitrate, fps))
+    pub fn cached_media_info(&self) -> Option<&MediaInfo> {
+        self.info.as_ref()
     }
 
-    pub async fn resolution(&self) -> anyhow::Result<(u32, u32)> {
-        let path = format!("input/{}.{}", self.id, self.from);
-
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=width,height",
-                "-of",
-                "csv=s=x:p=0",
-                &path,
-            ])
-            .output()
-            .await?;
-
-        let res_str = String::from_utf8(output.stdout)?;
-        let mut parts = res_str.trim().split('x');
-        let width = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("failed to get width"))?
-            .parse::<u32>()?;
-        let height = parts
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("failed to get height"))?
-            .parse::<u32>()?;
-
-        Ok((width, height))
+    // for when the input was probed before it got its final path, e.g. during an upload
+    pub fn set_media_info(&mut self, info: MediaInfo) {
+        let video = info.video();
+        self.total_frames = video.and_then(|v| v.frames);
+        self.bitrate = Some(video.and_then(|v| v.bit_rate).unwrap_or(DEFAULT_BITRATE));
+        self.fps = video
+            .and_then(|v| v.frame_rate)
+            .map(|fps| fps.round() as u32);
+        self.info = Some(info);
     }
 
-    pub async fn pix_fmt(&self) -> anyhow::Result<String> {
-        let path = format!("input/{}.{}", self.id, self.from);
-
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=pix_fmt",
-                "-of",
-                "default=nokey=1:noprint_wrappers=1",
-                &path,
-            ])
-            .output()
-            .await?;
-
-        let pix_fmt = String::from_utf8(output.stdout)?
-            .lines()
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("failed to get pixel format"))?
-            .to_string();
-
-        Ok(pix_fmt)
+    // TODO: scale based on resolution
+    pub async fn bitrate(&mut self) -> anyhow::Result<u64> {
+        self.media_info().await?;
+        let bitrate = self.bitrate.unwrap_or(DEFAULT_BITRATE);
+        Ok(((bitrate as f64) * BITRATE_MULTIPLIER) as u64)
     }
 
-    pub async fn codecs(&self) -> anyhow::Result<(String, String)> {
-        let path = format!("input/{}.{}", self.id, self.from);
-
-        // Video codec
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "v:0",
-                "-show_entries",
-                "stream=codec_name",
-                "-of",
-                "default=nokey=1:noprint_wrappers=1",
-                &path,
-            ])
-            .output()
-            .await?;
-
-        let video_codec = String::from_utf8(output.stdout)?
-            .lines()
-            .next()
-            .unwrap_or("none")
-            .to_string();
-
-        // Audio codec
-        let output = Command::new("ffprobe")
-            .args([
-                "-v",
-                "error",
-                "-select_streams",
-                "a:0",
-                "-show_entries",
-                "stream=codec_name",
-                "-of",
-                "default=nokey=1:noprint_wrappers=1",
-                &path,
-            ])
-            .output()
-            .await?;
+    pub async fn total_frames(&mut self) -> anyhow::Result<u64> {
+        self.media_info().await?;
+        self.total_frames
+            .ok_or_else(|| anyhow::anyhow!("Error parsing total frames from output"))
+    }
 
-        let audio_codec = String::from_utf8(output.stdout)?
-            .lines()
-            .next()
-            .unwrap_or("none")
-            .to_string();
+    pub async fn fps(&mut self) -> anyhow::Result<u32> {
+        self.media_info().await?;
+        self.fps
+            .ok_or_else(|| anyhow::anyhow!("failed to parse fps"))
+    }
 
-        Ok((video_codec, audio_codec))
+    pub async fn bitrate_and_fps(&mut self) -> anyhow::Result<(u64, u32)> {
+        let (bitrate, fps) = (self.bitrate().await?, self.fps().await?);
+        Ok((bitrate, fps))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data", rename_all = "camelCase")]
 pub enum ProgressUpdate {
     #[serde(rename = "frame", rename_all = "camelCase")]
     Frame(u64),
     #[serde(rename = "fps", rename_all = "camelCase")]
     FPS(f64),
+    // 0-100, based on how much of the input's duration has been encoded
+    #[serde(rename = "percent", rename_all = "camelCase")]
+    Percent(f64),
+    // estimated seconds left
+    #[serde(rename = "eta", rename_all = "camelCase")]
+    Eta(f64),
+    // bytes written to the output so far
+    #[serde(rename = "outputSize", rename_all = "camelCase")]
+    OutputSize(u64),
+    // encoding speed as a multiple of realtime
+    #[serde(rename = "speed", rename_all = "camelCase")]
+    Speed(f64),
     #[serde(rename = "error", rename_all = "camelCase")]
     Error(String),
 }
@@ -1,20 +1,28 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::config::config;
 use anyhow::anyhow;
 use format::{Conversion, ConverterFormat};
-use job::{Job, ProgressUpdate};
+use job::{ConversionDetails, Job, ProgressUpdate};
 use log::error;
 use log::info;
+use options::ConversionOptions;
 use speed::ConversionSpeed;
 use tokio::io::AsyncBufReadExt as _;
 use tokio::io::BufReader;
 use tokio::process::Command;
 use tokio::sync::mpsc;
 
+pub mod codec;
+pub mod edits;
 pub mod format;
+pub mod frames;
+pub mod gif;
 pub mod gpu;
 pub mod job;
+pub mod options;
+pub mod probe;
 pub mod speed;
 
 pub struct Converter {
@@ -37,28 +45,77 @@ impl Converter {
         }
     }
 
+    pub fn with_options(mut self, options: ConversionOptions) -> Self {
+        self.conversion = self.conversion.with_options(options);
+        self
+    }
+
+    // anything left to do once ffmpeg exited, which for now is packing up exported frames. the
+    // frames are removed either way
+    pub async fn finalize(&self, job: &Job, succeeded: bool) -> anyhow::Result<()> {
+        let (Some(_), Some(output_path)) = (&self.conversion.options.frames, job.output_path())
+        else {
+            return Ok(());
+        };
+        let dir = frames::frames_dir(&output_path);
+        let result = if succeeded {
+            frames::pack(dir.clone(), output_path).await
+        } else {
+            Ok(())
+        };
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            error!("failed to remove {}: {}", dir, e);
+        }
+        result
+    }
+
     pub async fn convert(
         &self,
         job: &mut Job,
         gpu: &gpu::ConverterGPU,
         vaapi_device_path: Option<&str>,
     ) -> anyhow::Result<(mpsc::Receiver<ProgressUpdate>, tokio::process::Child)> {
+        job.conversion = Some(ConversionDetails {
+            to: self.conversion.to.to_string(),
+            speed: self.speed.clone(),
+            keep_metadata: self.keep_metadata,
+            options: self.conversion.options.clone(),
+            gpu: gpu.to_string(),
+            ffmpeg_command: None,
+        });
+
         let (tx, rx) = mpsc::channel(1);
-        let input_filename = format!("input/{}.{}", job.id, self.conversion.from.to_string());
-        let output_filename = format!("output/{}.{}", job.id, self.conversion.to.to_string());
+        let directories = &config().directories;
+        let input_filename = format!("{}/{}.{}", directories.input, job.id, self.conversion.from);
+        let mut output_filename =
+            format!("{}/{}.{}", directories.output, job.id, self.conversion.to);
+        // zips are packed in finalize, ffmpeg only writes the frames
+        if let Some(frames) = &self.conversion.options.frames {
+            let dir = frames::frames_dir(&output_filename);
+            tokio::fs::create_dir_all(&dir).await?;
+            output_filename = frames::frame_pattern(&dir, frames.image);
+        }
         // let gpu = gpu::get_gpu().await;
         // let bitrate = job.bitrate().await?;
         // let fps = job.fps().await?;
         // the above but we run in parallel
-        let (bitrate, fps) = job.bitrate_and_fps().await?;
+        let info = job.media_info().await?.clone();
+        // audio-only inputs have neither, they can only go to audio formats which don't need them
+        let (bitrate, fps) = match info.video() {
+            Some(_) => job.bitrate_and_fps().await?,
+            None => (0, 0),
+        };
         let args = self
             .conversion
-            .to_args(&self.speed, gpu, bitrate, fps, job)
+            .to_args(&self.speed, gpu, bitrate, fps, &info)
             .await?;
         let args = args.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
         let args = args.as_slice();
-        let gpu_args = gpu.hwaccel_args(vaapi_device_path);
+        let vaapi_frames = args.iter().any(|arg| arg.ends_with("_vaapi"));
+        let gpu_args = gpu.hwaccel_args(vaapi_device_path, vaapi_frames);
         let gpu_args_refs: Vec<&str> = gpu_args.iter().map(|s| s.as_str()).collect();
+        let input_args = self.conversion.input_args();
+        let input_args: Vec<&str> = input_args.iter().map(|s| s.as_str()).collect();
 
         let metadata_args: &[&str] = if self.keep_metadata {
             &["-map_metadata", "0", "-map_chapters", "0"]
@@ -69,6 +126,7 @@ impl Converter {
         let command = &[
             &["-hide_banner", "-loglevel", "error", "-progress", "pipe:1"][..],
             &gpu_args_refs[..],
+            &input_args[..],
             &["-i", &input_filename],
             args,
             metadata_args,
@@ -81,6 +139,13 @@ impl Converter {
             .collect::<Vec<String>>();
 
         info!("running 'ffmpeg {}'", command.join(" "));
+        if let Some(conversion) = &mut job.conversion {
+            conversion.ffmpeg_command = Some(
+                std::iter::once("ffmpeg".to_string())
+                    .chain(command.iter().cloned())
+                    .collect(),
+            );
+        }
 
         let mut process = Command::new("ffmpeg")
             .args(command)
@@ -103,7 +168,9 @@ impl Converter {
             let mut lines = BufReader::new(stderr).lines();
             while let Ok(Some(line)) = lines.next_line().await {
                 error!("{}", line);
-                tx.send(ProgressUpdate::Error(line)).await.unwrap();
+                if tx.send(ProgressUpdate::Error(line)).await.is_err() {
+                    break;
+                }
             }
         });
 
@@ -115,25 +182,29 @@ impl Converter {
 
         let tx = Arc::clone(&tx_arc);
 
+        // how long the output is going to be, so we can turn out_time into a percentage
+        let duration = match &self.conversion.options.edits {
+            Some(edits) => edits.duration(info.duration),
+            None => info.duration,
+        }
+        .filter(|d| *d > 0.0);
+
         tokio::spawn(async move {
+            // ffmpeg prints one key=value per line and ends each block with a progress= line
             let mut lines = reader.lines();
-            while let Ok(Some(out)) = lines.next_line().await {
-                let mut map = HashMap::new();
-                for line in out.split("\n") {
-                    if let Some((k, v)) = line.split_once("=") {
-                        map.insert(k.trim(), v.trim());
-                    }
-                }
-
-                let mut reports = Vec::new();
-
-                if let Some(frame) = map.get("frame").and_then(|s| s.parse().ok()) {
-                    reports.push(ProgressUpdate::Frame(frame));
+            let mut block = HashMap::new();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some((k, v)) = line.split_once('=') else {
+                    continue;
+                };
+                let (k, v) = (k.trim().to_string(), v.trim().to_string());
+                if k != "progress" {
+                    block.insert(k, v);
+                    continue;
                 }
 
-                if let Some(fps) = map.get("fps").and_then(|s| s.parse().ok()) {
-                    reports.push(ProgressUpdate::FPS(fps));
-                }
+                let reports = progress_reports(&block, duration, v == "end");
+                block.clear();
 
                 for report in reports {
                     if tx.send(report).await.is_err() {
@@ -146,3 +217,53 @@ impl Converter {
         Ok((rx, process))
     }
 }
+
+fn progress_reports(
+    block: &HashMap<String, String>,
+    duration: Option<f64>,
+    finished: bool,
+) -> Vec<ProgressUpdate> {
+    let get = |key: &str| block.get(key).map(|s| s.as_str());
+    let mut reports = Vec::new();
+
+    if let Some(frame) = get("frame").and_then(|s| s.parse().ok()) {
+        reports.push(ProgressUpdate::Frame(frame));
+    }
+
+    if let Some(fps) = get("fps").and_then(|s| s.parse().ok()) {
+        reports.push(ProgressUpdate::FPS(fps));
+    }
+
+    if let Some(size) = get("total_size").and_then(|s| s.parse().ok()) {
+        reports.push(ProgressUpdate::OutputSize(size));
+    }
+
+    // "1.5x", or "N/A" right at the start
+    let speed = get("speed")
+        .and_then(|s| s.trim_end_matches('x').parse::<f64>().ok())
+        .filter(|s| *s > 0.0);
+    if let Some(speed) = speed {
+        reports.push(ProgressUpdate::Speed(speed));
+    }
+
+    // out_time_us is in microseconds despite older ffmpeg also printing a (wrong) out_time_ms
+    let out_time = get("out_time_us")
+        .and_then(|s| s.parse::<i64>().ok())
+        .map(|us| us.max(0) as f64 / 1_000_000.0);
+
+    if finished {
+        reports.push(ProgressUpdate::Percent(100.0));
+        reports.push(ProgressUpdate::Eta(0.0));
+    } else if let (Some(out_time), Some(duration)) = (out_time, duration) {
+        reports.push(ProgressUpdate::Percent(
+            (out_time / duration * 100.0).clamp(0.0, 100.0),
+        ));
+        if let Some(speed) = speed {
+            reports.push(ProgressUpdate::Eta(
+                ((duration - out_time) / speed).max(0.0),
+            ));
+        }
+    }
+
+    reports
+}
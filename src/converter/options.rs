@@ -0,0 +1,56 @@
+// everything a client can pick about a conversion on top of the format and speed. the websocket,
+// the http api, the cli and the kept file sidecars all flatten this in, so a new option only has
+// to be added here
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    codec::{self, AudioCodec, VideoCodec},
+    edits::Edits,
+    format::ConverterFormat,
+    frames::{self, FramesOptions},
+    gif::{self, GifOptions},
+};
+
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionOptions {
+    /// Video codec instead of the format's default (h264, hevc, av1 or vp9)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<VideoCodec>,
+
+    /// Audio codec instead of the format's default (aac, mp3, opus, vorbis or flac)
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<AudioCodec>,
+
+    // which frames to export, only for zip
+    #[command(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<FramesOptions>,
+
+    // only for gif, the speed picks whatever isn't set
+    #[command(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gif: Option<GifOptions>,
+
+    // trim, crop, scale, rotate and fps changes, in one filter chain
+    #[command(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edits: Option<Edits>,
+}
+
+impl ConversionOptions {
+    // everything that can be checked before the input is probed. the error is meant for the client
+    pub fn check(&self, to: ConverterFormat) -> Result<(), String> {
+        codec::check(to, self.video_codec, self.audio_codec)?;
+        frames::check(to, self.frames.as_ref())?;
+        gif::check(to, self.gif.as_ref())?;
+        if let Some(edits) = &self.edits {
+            edits.check()?;
+        }
+        Ok(())
+    }
+}
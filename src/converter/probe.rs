@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncReadExt as _, process::Command};
+
+use super::format::ConverterFormat;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FormatError {
+    #[error("unsupported container: {0}")]
+    Unsupported(String),
+    #[error("could not recognise the file's container format")]
+    Unrecognized,
+    #[error("failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+// raw `ffprobe -of json` output, only the fields we care about. ffprobe prints most numbers as
+// strings, so those get parsed when building `MediaInfo`
+#[derive(Debug, Deserialize)]
+pub struct ProbeOutput {
+    #[serde(default)]
+    pub streams: Vec<ProbeStream>,
+    pub format: Option<ProbeFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProbeStream {
+    pub index: u32,
+    pub codec_type: Option<String>,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    pub bit_rate: Option<String>,
+    pub nb_frames: Option<String>,
+    pub nb_read_packets: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    pub r_frame_rate: Option<String>,
+    pub bits_per_raw_sample: Option<String>,
+    pub bits_per_sample: Option<u32>,
+    pub color_transfer: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_space: Option<String>,
+    pub sample_rate: Option<String>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    #[serde(default)]
+    pub disposition: HashMap<String, u8>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub side_data_list: Vec<ProbeSideData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProbeSideData {
+    pub rotation: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProbeFormat {
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    pub duration: Option<String>,
+    pub size: Option<String>,
+    pub bit_rate: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Unknown,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub frames: Option<u64>,
+    pub language: Option<String>,
+    pub default: bool,
+    // cover art and thumbnails show up as video streams, but they're a single still image
+    pub attached_pic: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub pix_fmt: Option<String>,
+    pub frame_rate: Option<f64>,
+    pub bit_depth: Option<u32>,
+    pub color_transfer: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_space: Option<String>,
+    pub hdr: bool,
+    pub rotation: Option<i32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+}
+
+impl StreamInfo {
+    pub fn is_video(&self) -> bool {
+        self.kind == StreamKind::Video && !self.attached_pic
+    }
+
+    pub fn is_10bit(&self) -> bool {
+        self.bit_depth.is_some_and(|d| d > 8)
+    }
+
+    // width and height as the video is shown. ffmpeg applies the rotation before any filters
+    // run, so this is what the filters get to see
+    pub fn display_size(&self) -> (u32, u32) {
+        let (width, height) = (self.width.unwrap_or(0), self.height.unwrap_or(0));
+        match self.rotation.map(|r| r.rem_euclid(180)) {
+            Some(90) => (height, width),
+            _ => (width, height),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    pub duration: Option<f64>,
+    pub size: Option<u64>,
+    pub bit_rate: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+}
+
+impl MediaInfo {
+    pub async fn probe(path: &str) -> anyhow::Result<Self> {
+        Ok(probe(path).await?.into())
+    }
+
+    pub fn video(&self) -> Option<&StreamInfo> {
+        self.streams.iter().find(|s| s.is_video())
+    }
+
+    pub fn audio(&self) -> Option<&StreamInfo> {
+        self.streams.iter().find(|s| s.kind == StreamKind::Audio)
+    }
+}
+
+// "30000/1001" -> 29.97
+fn parse_rate(rate: &str) -> Option<f64> {
+    let rate = match rate.split_once('/') {
+        Some((num, den)) => {
+            let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+            if den == 0.0 {
+                return None;
+            }
+            num / den
+        }
+        None => rate.parse().ok()?,
+    };
+    (rate > 0.0).then_some(rate)
+}
+
+// yuv420p10le -> 10, falls back to 8 for anything without a depth suffix
+fn pix_fmt_depth(pix_fmt: &str) -> u32 {
+    if ["10le", "10be", "p010"].iter().any(|s| pix_fmt.contains(s)) {
+        10
+    } else if ["12le", "12be"].iter().any(|s| pix_fmt.contains(s)) {
+        12
+    } else {
+        8
+    }
+}
+
+impl From<ProbeStream> for StreamInfo {
+    fn from(s: ProbeStream) -> Self {
+        let kind = match s.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("data") => StreamKind::Data,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Unknown,
+        };
+
+        let raw_depth = s
+            .bits_per_raw_sample
+            .as_deref()
+            .and_then(|b| b.parse().ok())
+            .filter(|b| *b > 0);
+        let bit_depth = match kind {
+            StreamKind::Video => raw_depth.or_else(|| s.pix_fmt.as_deref().map(pix_fmt_depth)),
+            // only lossless codecs have one, lossy ones report 0
+            StreamKind::Audio => raw_depth.or(s.bits_per_sample.filter(|b| *b > 0)),
+            _ => None,
+        };
+
+        let hdr = matches!(
+            s.color_transfer.as_deref(),
+            Some("smpte2084") | Some("arib-std-b67")
+        );
+
+        // newer ffmpeg versions put it in the display matrix, older ones in a tag
+        let rotation = s
+            .side_data_list
+            .iter()
+            .find_map(|d| d.rotation)
+            .map(|r| r.round() as i32)
+            .or_else(|| s.tags.get("rotate").and_then(|r| r.parse().ok()));
+
+        let frames = s
+            .nb_read_packets
+            .as_deref()
+            .or(s.nb_frames.as_deref())
+            .and_then(|f| f.parse().ok());
+
+        Self {
+            index: s.index,
+            kind,
+            codec: s.codec_name,
+            codec_long_name: s.codec_long_name,
+            profile: s.profile,
+            bit_rate: s.bit_rate.and_then(|b| b.parse().ok()),
+            frames,
+            language: s.tags.get("language").cloned(),
+            default: s.disposition.get("default").copied().unwrap_or(0) == 1,
+            attached_pic: s.disposition.get("attached_pic").copied().unwrap_or(0) == 1,
+            width: s.width,
+            height: s.height,
+            pix_fmt: s.pix_fmt,
+            frame_rate: s.r_frame_rate.as_deref().and_then(parse_rate),
+            bit_depth,
+            color_transfer: s.color_transfer,
+            color_primaries: s.color_primaries,
+            color_space: s.color_space,
+            hdr,
+            rotation,
+            sample_rate: s.sample_rate.and_then(|r| r.parse().ok()),
+            channels: s.channels,
+            channel_layout: s.channel_layout,
+        }
+    }
+}
+
+impl From<ProbeOutput> for MediaInfo {
+    fn from(probe: ProbeOutput) -> Self {
+        let format = probe.format;
+        Self {
+            format_name: format
+                .as_ref()
+                .map(|f| f.format_name.clone())
+                .unwrap_or_default(),
+            format_long_name: format.as_ref().and_then(|f| f.format_long_name.clone()),
+            duration: format
+                .as_ref()
+                .and_then(|f| f.duration.as_deref())
+                .and_then(|d| d.parse().ok()),
+            size: format
+                .as_ref()
+                .and_then(|f| f.size.as_deref())
+                .and_then(|s| s.parse().ok()),
+            bit_rate: format
+                .as_ref()
+                .and_then(|f| f.bit_rate.as_deref())
+                .and_then(|b| b.parse().ok()),
+            streams: probe.streams.into_iter().map(StreamInfo::from).collect(),
+        }
+    }
+}
+
+// a single ffprobe call for everything we need to know about a file. packets are counted so
+// we get an accurate frame count even for containers which don't store it
+pub async fn probe(path: &str) -> anyhow::Result<ProbeOutput> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-count_packets",
+            "-show_streams",
+            "-show_format",
+            "-of",
+            "json",
+            path,
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+// ffprobe reports demuxers, not extensions, so one demuxer can stand for several of our formats.
+// the first entry of each family is what we pick when nothing else narrows it down
+fn demuxer_family(demuxer: &str) -> Option<&'static [ConverterFormat]> {
+    use ConverterFormat::*;
+    let family: &'static [ConverterFormat] = match demuxer {
+        "mov" | "mp4" | "m4a" | "3gp" | "3g2" | "mj2" => &[MP4, MOV, M4V, F4V, ThreeGP, ThreeG2],
+        "matroska" | "webm" => &[MKV, WebM],
+        "avi" => &[AVI, DIVX],
+        "asf" => &[WMV, ASF],
+        "mpegts" => &[TS, MTS, M2TS],
+        "mpeg" | "mpegvideo" => &[MPEG, MPG, VOB],
+        "flv" => &[FLV],
+        "gif" => &[GIF],
+        "ogg" => &[OGV, OGG, Opus],
+        "mp3" => &[MP3],
+        "aac" => &[AAC],
+        "flac" => &[FLAC],
+        "wav" => &[WAV],
+        "rm" => &[RM, RMVB],
+        "mxf" => &[MXF],
+        "nut" => &[NUT],
+        "swf" => &[SWF],
+        "amv" => &[AMV],
+        "h264" => &[H264],
+        "m4v" => &[M4V],
+        _ => return None,
+    };
+    Some(family)
+}
+
+// looks at the first few bytes of the file for well-known container signatures
+async fn sniff_magic(path: &str) -> Result<Option<ConverterFormat>, FormatError> {
+    let mut file = File::open(path).await?;
+    let mut buf = vec![0u8; 4096];
+    let mut len = 0;
+    while len < buf.len() {
+        let n = file.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    let buf = &buf[..len];
+    let at = |offset: usize, sig: &[u8]| buf.get(offset..offset + sig.len()) == Some(sig);
+
+    let format = if at(4, b"ftyp") {
+        match buf.get(8..12) {
+            Some(b"qt  ") => ConverterFormat::MOV,
+            Some(b"M4V ") | Some(b"M4VH") | Some(b"M4VP") => ConverterFormat::M4V,
+            Some(b"f4v ") => ConverterFormat::F4V,
+            Some(brand) if brand.starts_with(b"3gp") => ConverterFormat::ThreeGP,
+            Some(brand) if brand.starts_with(b"3g2") => ConverterFormat::ThreeG2,
+            _ => ConverterFormat::MP4,
+        }
+    } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+        // the EBML header carries the doctype close to the start
+        let header = &buf[..buf.len().min(64)];
+        if header.windows(4).any(|w| w == b"webm") {
+            ConverterFormat::WebM
+        } else {
+            ConverterFormat::MKV
+        }
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        ConverterFormat::AVI
+    } else if at(0, b"RIFF") && at(8, b"AMV ") {
+        ConverterFormat::AMV
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        ConverterFormat::GIF
+    } else if at(0, &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11]) {
+        ConverterFormat::WMV
+    } else if at(0, b"FLV\x01") {
+        ConverterFormat::FLV
+    } else if at(0, &[0x47]) && at(188, &[0x47]) {
+        ConverterFormat::TS
+    } else if at(4, &[0x47]) && at(196, &[0x47]) {
+        ConverterFormat::M2TS
+    } else if at(0, &[0x00, 0x00, 0x01, 0xBA]) {
+        ConverterFormat::MPEG
+    } else if at(0, b"OggS") {
+        // the first page holds the header of the first stream
+        if at(28, b"OpusHead") {
+            ConverterFormat::Opus
+        } else if at(28, b"\x01vorbis") {
+            ConverterFormat::OGG
+        } else {
+            ConverterFormat::OGV
+        }
+    } else if at(0, b"fLaC") {
+        ConverterFormat::FLAC
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        ConverterFormat::WAV
+    } else if at(0, b"ID3") {
+        ConverterFormat::MP3
+    } else if buf.len() >= 2 && buf[0] == 0xFF && buf[1] & 0xF6 == 0xF0 {
+        // an ADTS header, which looks like an mp3 frame with the layer bits cleared
+        ConverterFormat::AAC
+    } else if buf.len() >= 2 && buf[0] == 0xFF && buf[1] & 0xE0 == 0xE0 {
+        ConverterFormat::MP3
+    } else if at(0, b".RMF") {
+        ConverterFormat::RM
+    } else if at(0, b"nut/") {
+        ConverterFormat::NUT
+    } else if at(0, b"FWS") || at(0, b"CWS") || at(0, b"ZWS") {
+        ConverterFormat::SWF
+    } else if at(0, &[0x06, 0x0E, 0x2B, 0x34]) {
+        ConverterFormat::MXF
+    } else if at(0, &[0x00, 0x00, 0x00, 0x01, 0x67]) {
+        ConverterFormat::H264
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(format))
+}
+
+// figures out the container of the file at `path`, which `info` is the probe of. ffprobe gets the
+// final say on the family, the magic bytes pick a member of it and the extension `hint` is only
+// used when they can't
+pub async fn detect_format(
+    path: &str,
+    info: &MediaInfo,
+    hint: Option<&str>,
+) -> Result<ConverterFormat, FormatError> {
+    let hint = hint.and_then(|h| h.to_lowercase().parse::<ConverterFormat>().ok());
+    let magic = sniff_magic(path).await?;
+
+    let format_name = &info.format_name;
+    if format_name.is_empty() {
+        return magic.ok_or(FormatError::Unrecognized);
+    }
+
+    let family = format_name
+        .split(',')
+        .find_map(demuxer_family)
+        .ok_or_else(|| FormatError::Unsupported(format_name.to_string()))?;
+
+    let format = magic
+        .filter(|m| family.contains(m))
+        .or_else(|| hint.filter(|h| family.contains(h)))
+        .unwrap_or(family[0]);
+
+    log::debug!(
+        "detected {} as {} (ffprobe: {}, magic: {:?}, hint: {:?})",
+        path,
+        format,
+        format_name,
+        magic,
+        hint
+    );
+
+    Ok(format)
+}
@@ -1,9 +1,13 @@
 use log::warn;
 use serde::{Deserialize, Serialize};
 
-use super::{format::ConverterFormat, gpu::ConverterGPU};
+use super::{
+    format::ConverterFormat,
+    gif::{Dither, GifSettings},
+    gpu::ConverterGPU,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ConversionSpeed {
     UltraFast,
@@ -26,7 +30,77 @@ impl ConversionSpeed {
         }
     }
 
-    pub fn to_args(&self, to: &ConverterFormat, gpu: &ConverterGPU, bitrate: u64) -> Vec<String> {
+    // slower gets bigger, smoother gifs: more frames, more colours and error diffusion instead of
+    // the banding-prone ordered dither
+    pub fn gif_defaults(&self) -> GifSettings {
+        let (max_width, fps, colors, dither, per_frame_palette) = match self {
+            ConversionSpeed::UltraFast => (480, 10, 64, Dither::Bayer, false),
+            ConversionSpeed::Fast => (640, 12, 128, Dither::Bayer, false),
+            ConversionSpeed::Medium => (800, 15, 128, Dither::Sierra24A, false),
+            ConversionSpeed::Slow => (800, 20, 256, Dither::Sierra24A, false),
+            ConversionSpeed::Slower => (960, 24, 256, Dither::FloydSteinberg, false),
+            ConversionSpeed::VerySlow => (1080, 30, 256, Dither::FloydSteinberg, true),
+        };
+        GifSettings {
+            max_width,
+            fps,
+            colors,
+            dither,
+            loop_count: 0,
+            per_frame_palette,
+        }
+    }
+
+    // presets for an encoder the client picked through its codec, which can be a different one than
+    // the format would use on its own
+    fn encoder_args(&self, encoder: &str) -> Vec<String> {
+        let x264_preset = match self {
+            ConversionSpeed::UltraFast => "ultrafast",
+            ConversionSpeed::Fast => "fast",
+            ConversionSpeed::Medium => "medium",
+            ConversionSpeed::Slow => "slow",
+            ConversionSpeed::Slower => "slower",
+            ConversionSpeed::VerySlow => "veryslow",
+        };
+
+        let args: &[&str] = match encoder {
+            "libx264" | "libx265" => &["-preset", x264_preset],
+            // 0 is the slowest, 13 the fastest
+            "libsvtav1" => match self {
+                ConversionSpeed::UltraFast => &["-preset", "12"],
+                ConversionSpeed::Fast => &["-preset", "10"],
+                ConversionSpeed::Medium => &["-preset", "8"],
+                ConversionSpeed::Slow => &["-preset", "6"],
+                ConversionSpeed::Slower => &["-preset", "4"],
+                ConversionSpeed::VerySlow => &["-preset", "2"],
+            },
+            "libvpx-vp9" => match self {
+                ConversionSpeed::UltraFast => &["-speed", "5", "-row-mt", "1"],
+                ConversionSpeed::Fast => &["-speed", "4", "-row-mt", "1"],
+                ConversionSpeed::Medium => &["-speed", "3", "-row-mt", "1"],
+                ConversionSpeed::Slow => &["-speed", "2", "-row-mt", "1"],
+                ConversionSpeed::Slower => &["-speed", "1", "-row-mt", "1"],
+                ConversionSpeed::VerySlow => &["-speed", "0", "-row-mt", "1"],
+            },
+            // only "slow", "medium", and "fast" are supported
+            e if e.ends_with("_nvenc") => match self {
+                ConversionSpeed::VerySlow | ConversionSpeed::Slower => &["-preset", "slow"],
+                ConversionSpeed::Slow | ConversionSpeed::Medium => &["-preset", "medium"],
+                ConversionSpeed::Fast | ConversionSpeed::UltraFast => &["-preset", "fast"],
+            },
+            e if e.ends_with("_amf") => match self {
+                ConversionSpeed::UltraFast | ConversionSpeed::Fast => &["-quality", "speed"],
+                ConversionSpeed::Medium | ConversionSpeed::Slow => &["-quality", "balanced"],
+                ConversionSpeed::Slower | ConversionSpeed::VerySlow => &["-quality", "quality"],
+            },
+            e if e.ends_with("_qsv") => &["-preset", x264_preset],
+            // vaapi and videotoolbox have no presets
+            _ => &[],
+        };
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn format_args(&self, to: &ConverterFormat, gpu: &ConverterGPU) -> Vec<String> {
         let mut args = Vec::new();
 
         match to {
@@ -95,7 +169,61 @@ impl ConversionSpeed {
                 }
             }
 
-            ConverterFormat::GIF => {}
+            // gif quality lives in its filter, see gif_defaults
+            ConverterFormat::GIF | ConverterFormat::Zip => {}
+
+            ConverterFormat::WebP => {
+                // lossy quality, 100 is best
+                args.push("-quality".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("60".to_string()),
+                    ConversionSpeed::Fast => args.push("70".to_string()),
+                    ConversionSpeed::Medium => args.push("75".to_string()),
+                    ConversionSpeed::Slow => args.push("80".to_string()),
+                    ConversionSpeed::Slower => args.push("85".to_string()),
+                    ConversionSpeed::VerySlow => args.push("90".to_string()),
+                }
+                // encoder effort, 6 is the slowest and smallest
+                args.push("-compression_level".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("0".to_string()),
+                    ConversionSpeed::Fast => args.push("2".to_string()),
+                    ConversionSpeed::Medium => args.push("4".to_string()),
+                    ConversionSpeed::Slow => args.push("5".to_string()),
+                    ConversionSpeed::Slower | ConversionSpeed::VerySlow => {
+                        args.push("6".to_string())
+                    }
+                }
+            }
+
+            // lossless, slower only means smaller
+            ConverterFormat::APNG => {
+                args.push("-pred".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("none".to_string()),
+                    ConversionSpeed::Fast => args.push("sub".to_string()),
+                    ConversionSpeed::Medium | ConversionSpeed::Slow => {
+                        args.push("paeth".to_string())
+                    }
+                    ConversionSpeed::Slower | ConversionSpeed::VerySlow => {
+                        args.push("mixed".to_string())
+                    }
+                }
+            }
+
+            ConverterFormat::AVIF => {
+                args.extend(self.encoder_args("libsvtav1"));
+                // lower is better
+                args.push("-crf".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("40".to_string()),
+                    ConversionSpeed::Fast => args.push("36".to_string()),
+                    ConversionSpeed::Medium => args.push("32".to_string()),
+                    ConversionSpeed::Slow => args.push("30".to_string()),
+                    ConversionSpeed::Slower => args.push("28".to_string()),
+                    ConversionSpeed::VerySlow => args.push("26".to_string()),
+                }
+            }
 
             ConverterFormat::WebM | ConverterFormat::AVI | ConverterFormat::NUT => {
                 args.push("-speed".to_string());
@@ -134,9 +262,99 @@ impl ConversionSpeed {
             | ConverterFormat::ASF => {
                 warn!("{:?} format does not support speed settings", to);
             }
+
+            // slower means better quality here too, just like the video bitrate multiplier
+            ConverterFormat::MP3 => {
+                // vbr, 0 is best
+                args.push("-q:a".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("6".to_string()),
+                    ConversionSpeed::Fast => args.push("4".to_string()),
+                    ConversionSpeed::Medium => args.push("2".to_string()),
+                    ConversionSpeed::Slow => args.push("1".to_string()),
+                    ConversionSpeed::Slower | ConversionSpeed::VerySlow => {
+                        args.push("0".to_string())
+                    }
+                }
+            }
+
+            ConverterFormat::AAC => {
+                args.push("-b:a".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("128k".to_string()),
+                    ConversionSpeed::Fast => args.push("160k".to_string()),
+                    ConversionSpeed::Medium => args.push("192k".to_string()),
+                    ConversionSpeed::Slow => args.push("224k".to_string()),
+                    ConversionSpeed::Slower => args.push("256k".to_string()),
+                    ConversionSpeed::VerySlow => args.push("320k".to_string()),
+                }
+            }
+
+            ConverterFormat::Opus => {
+                args.push("-b:a".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("64k".to_string()),
+                    ConversionSpeed::Fast => args.push("96k".to_string()),
+                    ConversionSpeed::Medium => args.push("128k".to_string()),
+                    ConversionSpeed::Slow => args.push("160k".to_string()),
+                    ConversionSpeed::Slower => args.push("192k".to_string()),
+                    ConversionSpeed::VerySlow => args.push("256k".to_string()),
+                }
+                // encoder effort, 10 is the slowest and best
+                args.push("-compression_level".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("3".to_string()),
+                    ConversionSpeed::Fast => args.push("6".to_string()),
+                    ConversionSpeed::Medium => args.push("8".to_string()),
+                    _ => args.push("10".to_string()),
+                }
+            }
+
+            ConverterFormat::OGG => {
+                // vbr, 10 is best
+                args.push("-q:a".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("3".to_string()),
+                    ConversionSpeed::Fast => args.push("4".to_string()),
+                    ConversionSpeed::Medium => args.push("5".to_string()),
+                    ConversionSpeed::Slow => args.push("6".to_string()),
+                    ConversionSpeed::Slower => args.push("7".to_string()),
+                    ConversionSpeed::VerySlow => args.push("8".to_string()),
+                }
+            }
+
+            // lossless either way, slower only means smaller
+            ConverterFormat::FLAC => {
+                args.push("-compression_level".to_string());
+                match self {
+                    ConversionSpeed::UltraFast => args.push("0".to_string()),
+                    ConversionSpeed::Fast => args.push("3".to_string()),
+                    ConversionSpeed::Medium => args.push("5".to_string()),
+                    ConversionSpeed::Slow => args.push("8".to_string()),
+                    ConversionSpeed::Slower => args.push("10".to_string()),
+                    ConversionSpeed::VerySlow => args.push("12".to_string()),
+                }
+            }
+
+            ConverterFormat::WAV => {}
+        };
+
+        args
+    }
+
+    pub fn to_args(
+        &self,
+        to: &ConverterFormat,
+        gpu: &ConverterGPU,
+        bitrate: u64,
+        encoder: Option<&str>,
+    ) -> Vec<String> {
+        let mut args = match encoder {
+            Some(encoder) => self.encoder_args(encoder),
+            None => self.format_args(to, gpu),
         };
 
-        if *to != ConverterFormat::GIF {
+        if !to.is_image() && !to.is_audio() {
             args.push("-b:v".to_string());
             let bitrate = (bitrate as f64 * self.to_bitrate_mul()) as u64;
             args.push(bitrate.to_string());
@@ -1,13 +1,23 @@
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 use log::info;
-use services::{download::download, upload::upload, version::version, websocket::websocket};
+use services::{
+    admin::{delete_kept, download_kept, list_kept, rerun_kept},
+    download::download,
+    job::{cancel_job, info, job_status, start_job},
+    resumable::{append_upload, finalize_upload, init_upload, upload_status},
+    upload::upload,
+    version::version,
+    websocket::websocket,
+};
 
-use crate::http::services::keep::keep;
+use crate::{config::config, http::services::keep::keep};
 
 mod response;
 mod services;
 
+pub use services::download::forget_sent;
+
 pub async fn start_http() -> anyhow::Result<()> {
     let server = HttpServer::new(|| {
         App::new()
@@ -20,18 +30,26 @@ pub async fn start_http() -> anyhow::Result<()> {
             .service(
                 web::scope("/api")
                     .service(upload)
+                    .service(init_upload)
+                    .service(upload_status)
+                    .service(append_upload)
+                    .service(finalize_upload)
                     .service(download)
                     .service(websocket)
                     .service(version)
-                    .service(keep),
+                    .service(keep)
+                    .service(info)
+                    .service(start_job)
+                    .service(job_status)
+                    .service(cancel_job)
+                    .service(list_kept)
+                    .service(download_kept)
+                    .service(delete_kept)
+                    .service(rerun_kept),
             )
     });
-    let port = std::env::var("PORT").unwrap_or_else(|_| "24153".to_string());
-    if !port.chars().all(char::is_numeric) {
-        anyhow::bail!("PORT must be a number");
-    }
-    let ip = format!("0.0.0.0:{}", port);
-    info!("http server listening on {}", ip);
-    server.bind(ip)?.run().await?;
+    let addr = (config().server.bind_address, config().server.port);
+    info!("http server listening on {}:{}", addr.0, addr.1);
+    server.bind(addr)?.run().await?;
     Ok(())
 }
@@ -0,0 +1,157 @@
+// /admin/... endpoints for kept files. everything here needs
+// "Authorization: Bearer <admin password>", and is disabled while the admin password is unset or
+// still the default from .env.example
+
+use std::io::ErrorKind;
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder, ResponseError};
+use subtle::ConstantTimeEq;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::config,
+    converter::job::Job,
+    http::{
+        response::ApiResponse,
+        services::{
+            download::{serve_file, DownloadError},
+            job::{bearer_token, JobError, JobStatus, StartRequest},
+            upload::{finish_upload, UploadError},
+        },
+    },
+    kept::{self, sidecar_path, KeptFile},
+    runner::{self, StartOptions},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AdminError {
+    #[error("the admin api is disabled")]
+    Disabled,
+    #[error("invalid admin token")]
+    InvalidToken,
+    #[error("kept file not found")]
+    FileNotFound,
+    #[error("filesystem error: {0}")]
+    FilesystemError(#[from] std::io::Error),
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+    #[error(transparent)]
+    Upload(#[from] UploadError),
+    #[error(transparent)]
+    Job(#[from] JobError),
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        let status = match self {
+            AdminError::Disabled => actix_web::http::StatusCode::FORBIDDEN,
+            AdminError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+            AdminError::FileNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            AdminError::FilesystemError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            AdminError::Download(e) => return e.error_response(),
+            AdminError::Upload(e) => return e.error_response(),
+            AdminError::Job(e) => return e.error_response(),
+        };
+
+        HttpResponse::build(status).json(ApiResponse::<()>::Error(self.to_string()))
+    }
+}
+
+fn authorize(req: &HttpRequest) -> Result<(), AdminError> {
+    let password = config()
+        .server
+        .admin_password
+        .as_ref()
+        .map(|p| p.expose())
+        .filter(|p| *p != "supersecret")
+        .ok_or(AdminError::Disabled)?;
+
+    // constant time, so the password can't be guessed a byte at a time from response timings
+    let matches = bearer_token(req)
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(password.as_bytes())));
+    if !matches {
+        log::warn!("rejected admin request to {}", req.path());
+        return Err(AdminError::InvalidToken);
+    }
+    Ok(())
+}
+
+async fn kept_file(id: Uuid) -> Result<KeptFile, AdminError> {
+    kept::find(id).await?.ok_or(AdminError::FileNotFound)
+}
+
+#[get("/admin/kept")]
+pub async fn list_kept(req: HttpRequest) -> Result<impl Responder, AdminError> {
+    authorize(&req)?;
+    Ok(ApiResponse::Success(kept::list().await?))
+}
+
+// unlike /download, this leaves the file where it is
+#[get("/admin/kept/{id}")]
+pub async fn download_kept(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> Result<HttpResponse, AdminError> {
+    authorize(&req)?;
+    let file = kept_file(path.into_inner()).await?;
+    let filename = format!("{}.{}", file.id, file.format);
+    let format = file.format.parse().ok();
+    Ok(serve_file(&req, &file.path, &filename, format, None).await?)
+}
+
+#[delete("/admin/kept/{id}")]
+pub async fn delete_kept(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, AdminError> {
+    authorize(&req)?;
+    let file = kept_file(path.into_inner()).await?;
+    match fs::remove_file(&file.path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(AdminError::FileNotFound),
+        Err(e) => return Err(e.into()),
+    }
+    fs::remove_file(sidecar_path(&file.path)).await.ok();
+    log::info!("admin deleted kept file {}", file.path);
+    Ok(ApiResponse::Success(()))
+}
+
+// converts a copy of the kept file as a brand new job. the response has the job's token, so it
+// can be followed through /job/{id} or the websocket like any other
+#[post("/admin/kept/{id}/rerun")]
+pub async fn rerun_kept(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    body: web::Json<StartRequest>,
+) -> Result<impl Responder, AdminError> {
+    authorize(&req)?;
+    let file = kept_file(path.into_inner()).await?;
+    let body = body.into_inner();
+
+    let rand: [u8; 64] = rand::random();
+    let token = hex::encode(rand);
+    let mut job = Job::new(token.clone(), String::new());
+    job.filename = Some(format!("{}.{}", file.id, file.format));
+
+    let tmp_path = job.part_path();
+    fs::copy(&file.path, &tmp_path).await?;
+    let job = finish_upload(job, &tmp_path, Some(&file.format)).await?;
+    log::info!("re-running kept file {} as job {}", file.path, job.id);
+
+    let options = StartOptions {
+        to: body.to,
+        speed: body.speed,
+        keep_metadata: body.keep_metadata,
+        options: body.options,
+        callback_url: body.callback_url,
+    };
+    runner::start(job.id, &token, options)
+        .await
+        .map_err(JobError::from)?;
+
+    let (job, progress) = runner::status(job.id, &token)
+        .await
+        .map_err(JobError::from)?;
+    Ok(ApiResponse::Success(JobStatus { job, progress }))
+}
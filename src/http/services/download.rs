@@ -1,12 +1,39 @@
-// get /download/{id} where id is Uuid
+// get /download/{id}/{token} where id is Uuid. supports single range requests so interrupted
+// downloads can be resumed and previews can seek. a download counts once all of the file was
+// sent, and how many are allowed before the job goes away depends on VERTD_RETENTION
 
-use actix_web::{get, web, HttpResponse, Responder, ResponseError};
+use actix_web::{
+    body::SizedStream,
+    get,
+    http::header::{
+        self, ByteRangeSpec, Charset, ContentDisposition, DispositionParam, DispositionType,
+        EntityTag, ExtendedValue, Header, HttpDate, IfModifiedSince, IfNoneMatch, IfRange, Range,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
 use futures_util::stream::StreamExt;
-use tokio::{fs, time, time::Duration};
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    sync::{atomic, Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
 use tokio_util::io::ReaderStream;
-use std::sync::{Arc, atomic};
+use uuid::Uuid;
 
-use crate::{http::response::ApiResponse, state::APP_STATE};
+use crate::{
+    config::config, converter::format::ConverterFormat, http::response::ApiResponse,
+    state::APP_STATE,
+};
+
+// files stay around for a bit after the last allowed download, so a request that was already
+// in flight can still finish
+const REMOVAL_GRACE: Duration = Duration::from_secs(30);
 
 #[derive(Debug, thiserror::Error)]
 pub enum DownloadError {
@@ -14,8 +41,12 @@ pub enum DownloadError {
     JobNotFound,
     #[error("incomplete websocket handshake")]
     IncompleteHandshake,
+    #[error("the conversion hasn't finished")]
+    NotFinished,
     #[error("invalid token")]
     InvalidToken,
+    #[error("download limit reached")]
+    DownloadLimitReached,
     #[error("filesystem error: {0}")]
     FilesystemError(#[from] std::io::Error),
 }
@@ -25,7 +56,9 @@ impl ResponseError for DownloadError {
         let status = match self {
             DownloadError::JobNotFound => actix_web::http::StatusCode::NOT_FOUND,
             DownloadError::IncompleteHandshake => actix_web::http::StatusCode::BAD_REQUEST,
+            DownloadError::NotFinished => actix_web::http::StatusCode::CONFLICT,
             DownloadError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+            DownloadError::DownloadLimitReached => actix_web::http::StatusCode::GONE,
             DownloadError::FilesystemError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -33,8 +66,47 @@ impl ResponseError for DownloadError {
     }
 }
 
+lazy_static! {
+    // which bytes of each file have been sent so far, across all requests for it
+    static ref SENT: Mutex<HashMap<String, Vec<(u64, u64)>>> = Mutex::new(HashMap::new());
+}
+
+// drops what was sent of a file that is going away. files that are never downloaded in full
+// would otherwise stay in SENT forever
+pub fn forget_sent(file_path: &str) {
+    SENT.lock().unwrap().remove(file_path);
+}
+
+// adds the half-open range [start, end) and reports whether [0, size) is now fully covered
+fn mark_sent(file_path: &str, start: u64, end: u64, size: u64) -> bool {
+    let mut sent = SENT.lock().unwrap();
+    let ranges = sent.entry(file_path.to_string()).or_default();
+    if start < end {
+        ranges.push((start, end));
+    }
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for &(start, end) in ranges.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let complete = matches!(merged.as_slice(), [(0, end)] if *end >= size);
+    if complete {
+        sent.remove(file_path);
+    } else {
+        *ranges = merged;
+    }
+    complete
+}
+
 struct StreamGuard {
     file_path: String,
+    job_id: Uuid,
+    start: u64,
     bytes_sent: Arc<atomic::AtomicU64>,
     file_size: u64,
 }
@@ -42,65 +114,156 @@ struct StreamGuard {
 impl Drop for StreamGuard {
     fn drop(&mut self) {
         let total_sent = self.bytes_sent.load(atomic::Ordering::Relaxed);
-        let file_path = self.file_path.clone();
-        let file_size = self.file_size;
+        if !mark_sent(
+            &self.file_path,
+            self.start,
+            self.start + total_sent,
+            self.file_size,
+        ) {
+            return;
+        }
 
+        log::info!("all bytes successfully sent for {}", self.file_path);
+        let job_id = self.job_id;
         tokio::spawn(async move {
-            if total_sent == file_size {
-                log::info!("all bytes successfully sent for {}", file_path);
-                time::sleep(Duration::from_secs(30)).await;
-                log::info!("removing file after successful download: {}", file_path);
-                if let Err(e) = fs::remove_file(&file_path).await {
-                    log::error!("failed to remove file: {}", e);
+            let mut app_state = APP_STATE.lock().await;
+            app_state.jobs.update(&job_id, |job| {
+                job.downloads += 1;
+                // the janitor takes it from here
+                if config()
+                    .jobs
+                    .max_downloads()
+                    .is_some_and(|max| job.downloads >= max)
+                {
+                    log::info!("job {} reached its download limit", job_id);
+                    job.extend_lifetime(REMOVAL_GRACE);
                 }
-            }
+            });
         });
     }
 }
 
+enum Part {
+    Full,
+    // inclusive, like the Range header itself
+    Range(u64, u64),
+    Unsatisfiable,
+}
+
+fn typed_header<H: Header>(req: &HttpRequest) -> Option<H> {
+    if !req.headers().contains_key(H::name()) {
+        return None;
+    }
+    H::parse(req).ok()
+}
+
+fn not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: HttpDate) -> bool {
+    // If-None-Match wins over If-Modified-Since when both are sent
+    if let Some(if_none_match) = typed_header::<IfNoneMatch>(req) {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+
+    typed_header::<IfModifiedSince>(req)
+        .is_some_and(|IfModifiedSince(since)| last_modified <= since)
+}
+
+fn requested_part(req: &HttpRequest, size: u64, etag: &EntityTag, last_modified: HttpDate) -> Part {
+    let Some(Range::Bytes(specs)) = typed_header::<Range>(req) else {
+        return Part::Full;
+    };
+
+    // the file changed since the client got the first part of it, so it needs all of it again
+    if let Some(if_range) = typed_header::<IfRange>(req) {
+        let fresh = match if_range {
+            IfRange::EntityTag(tag) => tag.strong_eq(etag),
+            IfRange::Date(date) => date == last_modified,
+        };
+        if !fresh {
+            return Part::Full;
+        }
+    }
+
+    // browsers only ever ask for one range, multipart/byteranges isn't worth it
+    let [spec]: &[ByteRangeSpec] = specs.as_slice() else {
+        return Part::Full;
+    };
+
+    match spec.to_satisfiable_range(size) {
+        Some((start, end)) => Part::Range(start, end),
+        None => Part::Unsatisfiable,
+    }
+}
+
+fn content_disposition(filename: &str) -> ContentDisposition {
+    let mut parameters = vec![DispositionParam::Filename(
+        filename
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect(),
+    )];
+    // non-ascii names survive through filename*, which every browser understands
+    if !filename.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        }));
+    }
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    }
+}
+
 #[get("/download/{id}/{token}")]
-pub async fn download(path: web::Path<(String, String)>) -> Result<impl Responder, DownloadError> {
+pub async fn download(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse, DownloadError> {
     let (id, token) = path.into_inner();
 
-    let is_admin = std::env::var("ADMIN_PASSWORD")
-        .ok()
-        .is_some_and(|p| p == token && !p.is_empty() && p != "supersecret"); // disable admin if password is empty or default
+    let id = id.parse().map_err(|_| DownloadError::JobNotFound)?;
+    let app_state = APP_STATE.lock().await;
+    let job = app_state.jobs.get(&id).ok_or(DownloadError::JobNotFound)?;
+    drop(app_state);
 
-    let file_path = if is_admin {
-        // prevent path traversal by checking if valid UUID
-        let id_no_ext = id.split('.').next().unwrap_or(&id);
-        if uuid::Uuid::parse_str(id_no_ext).is_err() {
-            log::warn!("invalid UUID for download: {id}");
-            return Err(DownloadError::JobNotFound);
-        }
-        log::warn!("admin download used for id {id}");
-        format!("permanent/{id}")
-    } else {
-        let id = id.parse().map_err(|_| DownloadError::JobNotFound)?;
-        let app_state = APP_STATE.lock().await;
-        let job = app_state
-            .jobs
-            .get(&id)
-            .ok_or(DownloadError::JobNotFound)?
-            .clone();
-        drop(app_state);
-
-        if job.auth != token && !is_admin {
-            return Err(DownloadError::InvalidToken);
-        }
+    if job.auth != token {
+        return Err(DownloadError::InvalidToken);
+    }
 
-        let file_path = match job.to {
-            Some(to) => format!("output/{id}.{to}"),
-            None => return Err(DownloadError::IncompleteHandshake),
-        };
+    if config()
+        .jobs
+        .max_downloads()
+        .is_some_and(|max| job.downloads >= max)
+    {
+        return Err(DownloadError::DownloadLimitReached);
+    }
 
-        let mut app_state = APP_STATE.lock().await;
-        app_state.jobs.remove(&id);
-        drop(app_state);
-        file_path
+    let (Some(file_path), Some(filename)) = (job.output_path(), job.download_filename()) else {
+        return Err(DownloadError::IncompleteHandshake);
     };
+    // the output is still being written, or is whatever a failed run left behind
+    if !job.completed() {
+        return Err(DownloadError::NotFinished);
+    }
+    let format = job.to.as_deref().and_then(|to| to.parse().ok());
+    serve_file(&req, &file_path, &filename, format, Some(id)).await
+}
 
-    let file = fs::File::open(&file_path).await.map_err(|e| {
+// streams `file_path` as an attachment. with a `job_id` the download counts towards the job's
+// retention once all of it was sent, without one the file is left alone
+pub async fn serve_file(
+    req: &HttpRequest,
+    file_path: &str,
+    filename: &str,
+    format: Option<ConverterFormat>,
+    job_id: Option<Uuid>,
+) -> Result<HttpResponse, DownloadError> {
+    let mut file = fs::File::open(file_path).await.map_err(|e| {
         if e.kind() == std::io::ErrorKind::NotFound {
             DownloadError::JobNotFound
         } else {
@@ -113,10 +276,44 @@ pub async fn download(path: web::Path<(String, String)>) -> Result<impl Responde
         .await
         .map_err(DownloadError::FilesystemError)?;
     let file_size = metadata.len();
+    let modified = metadata.modified()?;
+    let last_modified = HttpDate::from(modified);
+    let mtime = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let etag = EntityTag::new_strong(format!("{:x}-{:x}", mtime, file_size));
+    let mime = format.map_or("application/octet-stream", |f| f.mime_type());
+
+    if not_modified(req, &etag, last_modified) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header(header::LastModified(last_modified))
+            .finish());
+    }
+
+    let (mut res, start, length) = match requested_part(req, file_size, &etag, last_modified) {
+        Part::Full => (HttpResponse::Ok(), 0, file_size),
+        Part::Range(start, end) => {
+            let mut res = HttpResponse::PartialContent();
+            res.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, file_size),
+            ));
+            (res, start, end - start + 1)
+        }
+        Part::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", file_size)))
+                .finish());
+        }
+    };
+
+    file.seek(SeekFrom::Start(start)).await?;
     let bytes_sent = Arc::new(atomic::AtomicU64::new(0));
     let bytes_sent_clone = bytes_sent.clone();
 
-    let file_stream = ReaderStream::new(file);
+    let file_stream = ReaderStream::new(file.take(length));
     let tracked_stream = file_stream.map(move |chunk| {
         if let Ok(ref bytes) = chunk {
             bytes_sent_clone.fetch_add(bytes.len() as u64, atomic::Ordering::Relaxed);
@@ -124,21 +321,25 @@ pub async fn download(path: web::Path<(String, String)>) -> Result<impl Responde
         chunk
     });
 
-    // remove file when stream is dropped
-    let guard = StreamGuard {
-        file_path: file_path.clone(),
+    // count the download once every byte of the file was sent
+    let guard = job_id.map(|job_id| StreamGuard {
+        file_path: file_path.to_string(),
+        job_id,
+        start,
         bytes_sent: bytes_sent.clone(),
         file_size,
-    };
+    });
 
     // keep guard alive while streaming
     let http_stream = tracked_stream.inspect(move |_| {
         let _ = &guard;
     });
 
-    Ok(HttpResponse::Ok()
-        .insert_header(("Content-Type", "application/octet-stream"))
-        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", id)))
-        .insert_header(("Content-Length", file_size))
-        .streaming(http_stream))
+    Ok(res
+        .insert_header((header::CONTENT_TYPE, mime))
+        .insert_header(content_disposition(filename))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(header::ETag(etag))
+        .insert_header(header::LastModified(last_modified))
+        .body(SizedStream::new(length, http_stream)))
 }
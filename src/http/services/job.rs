@@ -0,0 +1,174 @@
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder, ResponseError};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    converter::{job::Job, options::ConversionOptions, speed::ConversionSpeed},
+    http::response::ApiResponse,
+    runner::{self, JobProgress, RunError, StartOptions},
+    state::APP_STATE,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum JobError {
+    #[error("job not found")]
+    JobNotFound,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("the input file is gone, it's removed once the conversion is done")]
+    InputGone,
+    #[error("ffprobe failed to read file: {0}")]
+    ProbeFailed(#[from] anyhow::Error),
+    #[error(transparent)]
+    Run(#[from] RunError),
+}
+
+impl ResponseError for JobError {
+    fn error_response(&self) -> HttpResponse {
+        let status = match self {
+            JobError::JobNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            JobError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+            JobError::InputGone => actix_web::http::StatusCode::GONE,
+            JobError::ProbeFailed(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            JobError::Run(e) => match e {
+                RunError::JobNotFound => actix_web::http::StatusCode::NOT_FOUND,
+                RunError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+                RunError::AlreadyCompleted | RunError::AlreadyRunning | RunError::NotRunning => {
+                    actix_web::http::StatusCode::CONFLICT
+                }
+                RunError::InvalidInputFormat
+                | RunError::InvalidOutputFormat
+                | RunError::InvalidCallbackUrl
+                | RunError::InvalidOptions(_) => actix_web::http::StatusCode::BAD_REQUEST,
+                RunError::CallbackUrlsDisabled => actix_web::http::StatusCode::FORBIDDEN,
+                RunError::GpuNotInitialized => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        };
+
+        HttpResponse::build(status).json(ApiResponse::<()>::Error(self.to_string()))
+    }
+}
+
+// job endpoints take the job token as "Authorization: Bearer <token>"
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+#[get("/job/{id}/info")]
+pub async fn info(req: HttpRequest, path: web::Path<Uuid>) -> Result<impl Responder, JobError> {
+    let id = path.into_inner();
+    let mut job = {
+        let app_state = APP_STATE.lock().await;
+        app_state.jobs.get(&id).ok_or(JobError::JobNotFound)?
+    };
+
+    if bearer_token(&req) != Some(job.auth.as_str()) {
+        return Err(JobError::InvalidToken);
+    }
+
+    // uploads are probed up front and the probe is stored with the job, so this only runs
+    // ffprobe for jobs from before that. the result is written back for next time
+    if let Some(info) = job.cached_media_info() {
+        return Ok(ApiResponse::Success(info.clone()));
+    }
+    if fs::metadata(job.input_path()).await.is_err() {
+        return Err(JobError::InputGone);
+    }
+    let info = job.media_info().await?.clone();
+    let mut app_state = APP_STATE.lock().await;
+    app_state
+        .jobs
+        .update(&id, |stored| stored.set_media_info(info.clone()));
+    drop(app_state);
+
+    Ok(ApiResponse::Success(info))
+}
+
+fn default_keep_metadata() -> bool {
+    true
+}
+
+// same as the websocket's StartJob, minus what's already in the path and the Authorization header
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartRequest {
+    pub to: String,
+    pub speed: ConversionSpeed,
+    #[serde(default = "default_keep_metadata")]
+    pub keep_metadata: bool,
+    #[serde(flatten)]
+    pub options: ConversionOptions,
+    #[serde(default)]
+    pub callback_url: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    #[serde(flatten)]
+    pub job: Job,
+    // only there while the job is queued or converting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+}
+
+async fn load_status(req: &HttpRequest, id: Uuid) -> Result<JobStatus, JobError> {
+    let token = bearer_token(req).ok_or(JobError::InvalidToken)?;
+    let (mut job, progress) = runner::status(id, token).await?;
+    // the command has the server's own paths in it, only kept files and the admin api show it
+    if let Some(conversion) = &mut job.conversion {
+        conversion.ffmpeg_command = None;
+    }
+    Ok(JobStatus { job, progress })
+}
+
+// for clients that can't keep a websocket open, poll GET /job/{id} afterwards
+#[post("/job/{id}/start")]
+pub async fn start_job(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+    body: web::Json<StartRequest>,
+) -> Result<impl Responder, JobError> {
+    let id = path.into_inner();
+    let body = body.into_inner();
+    let token = bearer_token(&req).ok_or(JobError::InvalidToken)?;
+
+    // the job keeps running without anyone listening
+    let options = StartOptions {
+        to: body.to,
+        speed: body.speed,
+        keep_metadata: body.keep_metadata,
+        options: body.options,
+        callback_url: body.callback_url,
+    };
+    runner::start(id, token, options).await?;
+
+    Ok(ApiResponse::Success(load_status(&req, id).await?))
+}
+
+#[get("/job/{id}")]
+pub async fn job_status(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, JobError> {
+    Ok(ApiResponse::Success(
+        load_status(&req, path.into_inner()).await?,
+    ))
+}
+
+// cancelling is asynchronous, the job disappears once ffmpeg has been stopped
+#[delete("/job/{id}")]
+pub async fn cancel_job(
+    req: HttpRequest,
+    path: web::Path<Uuid>,
+) -> Result<impl Responder, JobError> {
+    let id = path.into_inner();
+    let token = bearer_token(&req).ok_or(JobError::InvalidToken)?;
+    runner::cancel(id, token).await?;
+    Ok(ApiResponse::Success(()))
+}
@@ -1,16 +1,17 @@
 // get /download/{id} where id is Uuid
 
-use actix_web::{
-    post,
-    web::Json,
-    HttpResponse, Responder, ResponseError,
-};
-use discord_webhook2::{message, webhook::DiscordWebhook};
+use actix_web::{post, web::Json, HttpResponse, Responder, ResponseError};
 use serde::Deserialize;
 use tokio::fs;
 use uuid::Uuid;
 
-use crate::{http::response::ApiResponse, state::APP_STATE};
+use crate::{
+    config::config,
+    http::response::ApiResponse,
+    kept::{sidecar_path, KeptFileInfo},
+    notify::{notify, NotifyEvent},
+    state::APP_STATE,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum KeepError {
@@ -20,6 +21,8 @@ pub enum KeepError {
     InvalidToken,
     #[error("job is not in an error state")]
     NotErrored,
+    #[error("file was already kept")]
+    AlreadyKept,
     #[error("filesystem error: {0}")]
     FilesystemError(#[from] std::io::Error),
 }
@@ -35,6 +38,7 @@ impl ResponseError for KeepError {
         let status = match self {
             KeepError::JobNotFound => actix_web::http::StatusCode::NOT_FOUND,
             KeepError::NotErrored => actix_web::http::StatusCode::BAD_REQUEST,
+            KeepError::AlreadyKept => actix_web::http::StatusCode::CONFLICT,
             KeepError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
             KeepError::FilesystemError(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -47,9 +51,9 @@ impl ResponseError for KeepError {
 #[post("/keep")]
 pub async fn keep(body: Json<KeepRequest>) -> Result<impl Responder, KeepError> {
     let body = body.into_inner();
-    let app_state = APP_STATE.lock().await;
+    let mut app_state = APP_STATE.lock().await;
 
-    let job = app_state.jobs.get(&body.id).ok_or(KeepError::JobNotFound)?;
+    let mut job = app_state.jobs.get(&body.id).ok_or(KeepError::JobNotFound)?;
 
     if !job.errored() {
         return Err(KeepError::NotErrored);
@@ -59,48 +63,53 @@ pub async fn keep(body: Json<KeepRequest>) -> Result<impl Responder, KeepError>
         return Err(KeepError::InvalidToken);
     }
 
+    if job.kept {
+        return Err(KeepError::AlreadyKept);
+    }
+
+    // claimed before the lock goes, so a second request can't move the file at the same time.
+    // the moving and writing happens without the lock, nobody else should have to wait for it
+    job.kept = true;
+    app_state.jobs.insert(job.clone());
+    drop(app_state);
+
     // move the file from temp to permanent storage
-    let current_path = format!("input/{}.{}", job.id, job.from);
-    let permanent_path = format!("permanent/{}.{}", job.id, job.from);
+    let current_path = job.input_path();
+    let permanent_path = format!("{}/{}.{}", config().directories.permanent, job.id, job.from);
     log::debug!(
         "moving file to permanent storage: {} -> {}",
         current_path,
         permanent_path
     );
-    fs::rename(&current_path, &permanent_path).await?;
+    if let Err(e) = fs::rename(&current_path, &permanent_path).await {
+        let mut app_state = APP_STATE.lock().await;
+        app_state.jobs.update(&job.id, |job| job.kept = false);
+        return Err(e.into());
+    }
     log::info!("moved file to permanent storage: {}", permanent_path);
 
-    let id = job.id;
-    let from = job.from.clone();
+    // the file is what matters, so a missing sidecar is only worth a log line
+    let info = serde_json::to_vec_pretty(&KeptFileInfo::new(&job)).map_err(std::io::Error::from);
+    let written = match info {
+        Ok(info) => fs::write(sidecar_path(&permanent_path), info).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        log::error!("failed to write sidecar for {}: {}", permanent_path, e);
+    }
 
-    tokio::spawn(async move {
-        if let Err(e) = webhook_permanent(id, from).await {
-            log::error!("failed to send permanent webhook: {}", e);
-        }
+    notify(NotifyEvent::FileKept {
+        job_id: job.id,
+        url: admin_url(job.id),
+        from: job.from,
     });
 
     Ok("{}")
 }
 
-async fn webhook_permanent(id: Uuid, from: String) -> anyhow::Result<()> {
-    let webhook_url = std::env::var("WEBHOOK_URL")?;
-    let webhook_pings = std::env::var("WEBHOOK_PINGS").unwrap_or_default();
-    let admin_password = std::env::var("ADMIN_PASSWORD")?;
-    let public_url = std::env::var("PUBLIC_URL")?;
-
-    let file_url = format!("{public_url}/api/download/{id}.{from}/{admin_password}");
-
-    let client = DiscordWebhook::new(webhook_url)?;
-
-    let message = message::Message::new(|m| {
-        m.content(format!("🚨🚨🚨 {webhook_pings}")).embed(|e| {
-            e.title("a file has been kept permanently!")
-                .description(format!("download it [here]({file_url}). please note that the link contains a secret token, and also that the file is deleted upon first download, so please agree on whoever downloads it first."))
-                .color(0xff83fa)
-        })
-    });
-
-    client.send(&message).await?;
-
-    Ok(())
+// where admins can get the file from, if we know the public url. it needs the admin token as a
+// header, so the link itself is safe to share
+fn admin_url(id: Uuid) -> Option<String> {
+    let public_url = config().server.public_url.as_ref()?.expose();
+    Some(format!("{public_url}/api/admin/kept/{id}"))
 }
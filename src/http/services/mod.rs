@@ -1,5 +1,8 @@
+pub mod admin;
 pub mod download;
+pub mod job;
 pub mod keep;
+pub mod resumable;
 pub mod upload;
 pub mod version;
 pub mod websocket;
@@ -0,0 +1,265 @@
+// resumable uploads: POST /upload/init, then PATCH /upload/{id}/{token} with an
+// Upload-Offset header until everything is sent, then POST /upload/{id}/{token}/finalize
+
+use actix_web::{get, patch, post, web, HttpRequest, Responder};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, OpenOptions};
+use uuid::Uuid;
+
+use crate::{
+    config::config,
+    converter::job::Job,
+    http::{
+        response::ApiResponse,
+        services::upload::{extension_hint, finish_upload, write_stream, UploadError},
+    },
+    state::{PendingUpload, APP_STATE},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct InitRequest {
+    pub filename: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadStatus {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub offset: u64,
+    pub size: u64,
+}
+
+#[post("/upload/init")]
+pub async fn init_upload(body: web::Json<InitRequest>) -> Result<impl Responder, UploadError> {
+    let body = body.into_inner();
+    let max_size = config().limits.max_upload_size;
+    if body.size > max_size {
+        return Err(UploadError::TooLarge(max_size));
+    }
+
+    // the extension hint is kept in `from` until the upload is finalized and sniffed
+    let hint = extension_hint(&body.filename).unwrap_or_default();
+    let rand: [u8; 64] = rand::random();
+    let token = hex::encode(rand);
+    let mut job = Job::new(token.clone(), hint);
+    job.filename = Some(body.filename.clone());
+
+    fs::File::create(job.part_path()).await?;
+
+    let id = job.id;
+    let mut app_state = APP_STATE.lock().await;
+    app_state.uploads.insert(
+        id,
+        PendingUpload {
+            job,
+            size: body.size,
+            offset: 0,
+            writing: false,
+        },
+    );
+    drop(app_state);
+
+    info!(
+        "started resumable upload {} for {} ({} bytes)",
+        id, body.filename, body.size
+    );
+
+    Ok(ApiResponse::Success(UploadStatus {
+        id,
+        token: Some(token),
+        offset: 0,
+        size: body.size,
+    }))
+}
+
+// the claim on an upload while a PATCH writes to it. it has to be handed back however the request
+// ends, including when the client hangs up and actix drops the handler halfway through
+struct WriteClaim {
+    id: Uuid,
+    part_path: String,
+    released: bool,
+}
+
+impl WriteClaim {
+    async fn release(mut self, offset: u64) {
+        self.released = true;
+        let mut app_state = APP_STATE.lock().await;
+        if let Some(upload) = app_state.uploads.get_mut(&self.id) {
+            upload.offset = offset;
+            upload.writing = false;
+            // uploads are only abandoned once nothing was sent for a whole input lifetime
+            upload.job.extend_lifetime(config().jobs.input_lifetime);
+        }
+    }
+}
+
+impl Drop for WriteClaim {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let id = self.id;
+        let part_path = std::mem::take(&mut self.part_path);
+        // nothing tracked how far the write got, so the file itself is the offset
+        tokio::spawn(async move {
+            let len = fs::metadata(&part_path).await.map(|m| m.len()).ok();
+            let mut app_state = APP_STATE.lock().await;
+            if let Some(upload) = app_state.uploads.get_mut(&id) {
+                if let Some(len) = len {
+                    upload.offset = len.min(upload.size);
+                }
+                upload.writing = false;
+            }
+        });
+    }
+}
+
+// a write from a dropped request can still land after its offset was recorded, so anything past
+// the offset is cut off before the file is used again
+async fn trim_part(path: &str, offset: u64) -> std::io::Result<()> {
+    let file = OpenOptions::new().write(true).open(path).await?;
+    if file.metadata().await?.len() > offset {
+        file.set_len(offset).await?;
+    }
+    Ok(())
+}
+
+#[get("/upload/{id}/{token}")]
+pub async fn upload_status(path: web::Path<(Uuid, String)>) -> Result<impl Responder, UploadError> {
+    let (id, token) = path.into_inner();
+    let app_state = APP_STATE.lock().await;
+    let upload = app_state
+        .uploads
+        .get(&id)
+        .ok_or(UploadError::UploadNotFound)?;
+
+    if upload.job.auth != token {
+        return Err(UploadError::InvalidToken);
+    }
+
+    Ok(ApiResponse::Success(UploadStatus {
+        id,
+        token: None,
+        offset: upload.offset,
+        size: upload.size,
+    }))
+}
+
+#[patch("/upload/{id}/{token}")]
+pub async fn append_upload(
+    req: HttpRequest,
+    path: web::Path<(Uuid, String)>,
+    mut payload: web::Payload,
+) -> Result<impl Responder, UploadError> {
+    let (id, token) = path.into_inner();
+
+    let client_offset = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    // claim the upload so two requests can't append at the same time
+    let (job, offset, size) = {
+        let mut app_state = APP_STATE.lock().await;
+        let upload = app_state
+            .uploads
+            .get_mut(&id)
+            .ok_or(UploadError::UploadNotFound)?;
+
+        if upload.job.auth != token {
+            return Err(UploadError::InvalidToken);
+        }
+
+        if upload.writing {
+            return Err(UploadError::Busy);
+        }
+
+        if client_offset != Some(upload.offset) {
+            return Err(UploadError::OffsetMismatch(upload.offset));
+        }
+
+        upload.writing = true;
+        (upload.job.clone(), upload.offset, upload.size)
+    };
+
+    let claim = WriteClaim {
+        id,
+        part_path: job.part_path(),
+        released: false,
+    };
+
+    trim_part(&job.part_path(), offset).await?;
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(job.part_path())
+        .await?;
+
+    // whatever made it to disk counts, even if the connection drops halfway through. a chunk
+    // that failed halfway isn't in `written`, so it's cut off again
+    let mut written = offset;
+    let result = write_stream(&mut payload, &mut file, &mut written, size).await;
+    if result.is_err() {
+        file.set_len(written).await.ok();
+        file.sync_all().await.ok();
+    }
+    drop(file);
+
+    claim.release(written).await;
+    result?;
+
+    Ok(ApiResponse::Success(UploadStatus {
+        id,
+        token: None,
+        offset: written,
+        size,
+    }))
+}
+
+#[post("/upload/{id}/{token}/finalize")]
+pub async fn finalize_upload(
+    path: web::Path<(Uuid, String)>,
+) -> Result<impl Responder, UploadError> {
+    let (id, token) = path.into_inner();
+
+    let upload = {
+        let mut app_state = APP_STATE.lock().await;
+        let upload = app_state
+            .uploads
+            .get(&id)
+            .ok_or(UploadError::UploadNotFound)?;
+
+        if upload.job.auth != token {
+            return Err(UploadError::InvalidToken);
+        }
+
+        if upload.writing {
+            return Err(UploadError::Busy);
+        }
+
+        if upload.offset != upload.size {
+            return Err(UploadError::Incomplete {
+                offset: upload.offset,
+                size: upload.size,
+            });
+        }
+
+        app_state.uploads.remove(&id).unwrap()
+    };
+
+    let job = upload.job;
+    let hint = Some(job.from.clone()).filter(|h| !h.is_empty());
+    let tmp_path = job.part_path();
+    trim_part(&tmp_path, upload.size).await?;
+    let job = finish_upload(job, &tmp_path, hint.as_deref()).await?;
+    info!(
+        "finalized resumable upload {} ({} bytes, detected as {})",
+        id, upload.size, job.from
+    );
+
+    Ok(ApiResponse::Success(job))
+}
@@ -1,17 +1,24 @@
 use crate::{
-    converter::{format::ConverterFormat, job::Job},
+    config::config,
+    converter::{
+        job::Job,
+        probe::{detect_format, FormatError, MediaInfo, StreamInfo, StreamKind},
+    },
     http::response::ApiResponse,
     state::APP_STATE,
 };
 use actix_multipart::Multipart;
-use actix_web::{post, HttpResponse, Responder, ResponseError};
-use futures_util::StreamExt as _;
+use actix_web::{post, web::Bytes, HttpRequest, HttpResponse, Responder, ResponseError};
+use futures_util::{Stream, StreamExt as _};
 use log::info;
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
 };
 
+// anything above this is almost certainly not a normal video
+const MAX_STREAMS: usize = 64;
+
 #[derive(Debug, thiserror::Error)]
 pub enum UploadError {
     #[error("no file uploaded")]
@@ -20,10 +27,39 @@ pub enum UploadError {
     GetField(#[from] actix_multipart::MultipartError),
     #[error("no filename provided")]
     NoFilename,
-    #[error("missing file extension")]
-    NoExtension,
-    #[error("invalid file extension: {0}. allowed: jpg, png, gif")]
-    InvalidExtension(String),
+    #[error("{0}")]
+    UnsupportedFormat(#[from] FormatError),
+    #[error("file does not contain a decodable video or audio stream")]
+    NoMediaStream,
+    #[error("invalid video dimensions: {width}x{height}")]
+    InvalidDimensions { width: u32, height: u32 },
+    #[error("video resolution {width}x{height} exceeds the maximum of {max_width}x{max_height}")]
+    ResolutionTooLarge {
+        width: u32,
+        height: u32,
+        max_width: u32,
+        max_height: u32,
+    },
+    #[error("could not determine the video's duration")]
+    UnknownDuration,
+    #[error("video is too long ({duration:.0}s, max {max}s)")]
+    TooLong { duration: f64, max: u64 },
+    #[error("file has too many streams ({count}, max {max})")]
+    TooManyStreams { count: usize, max: usize },
+    #[error("file is too large (max {0} bytes)")]
+    TooLarge(u64),
+    #[error("upload not found")]
+    UploadNotFound,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("offset mismatch, current offset is {0}")]
+    OffsetMismatch(u64),
+    #[error("another chunk is currently being written to this upload")]
+    Busy,
+    #[error("upload is incomplete ({offset} of {size} bytes received)")]
+    Incomplete { offset: u64, size: u64 },
+    #[error("failed to read request body")]
+    ReadPayload(#[from] actix_web::error::PayloadError),
     #[error("failed to read file data")]
     GetChunk(#[from] actix_web::Error),
     #[error("internal server error while writing file")]
@@ -37,6 +73,12 @@ impl ResponseError for UploadError {
         // change these status codes as needed
         let status = match self {
             UploadError::GetField(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            UploadError::TooLarge(_) => actix_web::http::StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::UploadNotFound => actix_web::http::StatusCode::NOT_FOUND,
+            UploadError::InvalidToken => actix_web::http::StatusCode::UNAUTHORIZED,
+            UploadError::OffsetMismatch(_) | UploadError::Busy => {
+                actix_web::http::StatusCode::CONFLICT
+            }
             UploadError::GetChunk(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             UploadError::WriteFile(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             _ => actix_web::http::StatusCode::BAD_REQUEST,
@@ -46,8 +88,167 @@ impl ResponseError for UploadError {
     }
 }
 
+// makes sure the input is something we can actually convert before a job is created for it
+fn validate_input(info: &MediaInfo) -> Result<(), UploadError> {
+    if info.streams.len() > MAX_STREAMS {
+        return Err(UploadError::TooManyStreams {
+            count: info.streams.len(),
+            max: MAX_STREAMS,
+        });
+    }
+
+    let limits = &config().limits;
+    let decodable = |s: &&StreamInfo| s.codec.is_some();
+    match info.streams.iter().filter(|s| s.is_video()).find(decodable) {
+        Some(video) => {
+            let (width, height) = (video.width.unwrap_or(0), video.height.unwrap_or(0));
+            if width == 0 || height == 0 {
+                return Err(UploadError::InvalidDimensions { width, height });
+            }
+
+            let (max_width, max_height) =
+                (limits.max_resolution.width, limits.max_resolution.height);
+            let (long, short) = (width.max(height), width.min(height));
+            if long > max_width.max(max_height) || short > max_width.min(max_height) {
+                return Err(UploadError::ResolutionTooLarge {
+                    width,
+                    height,
+                    max_width,
+                    max_height,
+                });
+            }
+        }
+        // audio-only files are fine, they can be converted to audio formats
+        None => {
+            info.streams
+                .iter()
+                .filter(|s| s.kind == StreamKind::Audio)
+                .find(decodable)
+                .ok_or(UploadError::NoMediaStream)?;
+        }
+    }
+
+    if let Some(max) = limits.max_duration {
+        let duration = info.duration.ok_or(UploadError::UnknownDuration)?;
+        if duration > max as f64 {
+            return Err(UploadError::TooLong { duration, max });
+        }
+    }
+
+    Ok(())
+}
+
+// appends the stream chunk by chunk to `file`, bailing out as soon as `max_size` is exceeded.
+// `written` is updated as we go so callers know how much made it to disk even on failure
+pub async fn write_stream<S, E>(
+    stream: &mut S,
+    file: &mut File,
+    written: &mut u64,
+    max_size: u64,
+) -> Result<(), UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    UploadError: From<E>,
+{
+    while let Some(chunk) = stream.next().await {
+        let data = chunk?;
+        if *written + data.len() as u64 > max_size {
+            return Err(UploadError::TooLarge(max_size));
+        }
+        file.write_all(&data).await?;
+        *written += data.len() as u64;
+    }
+    file.flush().await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+// the extension is only used as a hint when detecting the container, so it's fine if it's missing
+pub fn extension_hint(filename: &str) -> Option<String> {
+    let (_, ext) = filename.rsplit_once('.')?;
+    let ext = ext
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    (!ext.is_empty()).then_some(ext)
+}
+
+// detects the container of a fully written `tmp_path`, moves it to its final place in input/ and
+// validates it. only then is the job made visible, otherwise the file is deleted straight away
+pub async fn finish_upload(
+    mut job: Job,
+    tmp_path: &str,
+    hint: Option<&str>,
+) -> Result<Job, UploadError> {
+    // the one ffprobe run, anything it can't read is rejected right away. it's cached on the job
+    // afterwards, so neither the validation nor the conversion has to run ffprobe again
+    let probed = match MediaInfo::probe(tmp_path).await {
+        Ok(info) => detect_format(tmp_path, &info, hint)
+            .await
+            .map(|format| (info, format))
+            .map_err(UploadError::from),
+        Err(e) => Err(e.into()),
+    };
+    let (info, format) = match probed {
+        Ok(probed) => probed,
+        Err(e) => {
+            fs::remove_file(tmp_path).await.ok();
+            return Err(e);
+        }
+    };
+
+    job.from = format.to_string();
+    let input_path = job.input_path();
+    fs::rename(tmp_path, &input_path).await?;
+    job.set_media_info(info);
+
+    let validated = match job.media_info().await {
+        Ok(info) => validate_input(info).map(|()| info.video().is_some()),
+        Err(e) => Err(e.into()),
+    };
+    let validated = match validated {
+        Ok(true) => job
+            .total_frames()
+            .await
+            .map(|_| ())
+            .map_err(UploadError::from),
+        // no frames to count, progress goes by duration anyway
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = validated {
+        log::warn!("rejected upload {}: {}", job.id, e);
+        fs::remove_file(&input_path).await.ok();
+        return Err(e);
+    }
+
+    job.extend_lifetime(config().jobs.input_lifetime);
+    let mut app_state = APP_STATE.lock().await;
+    app_state.jobs.insert(job.clone());
+    drop(app_state);
+
+    Ok(job)
+}
+
 #[post("/upload")]
-pub async fn upload(mut payload: Multipart) -> Result<impl Responder, UploadError> {
+pub async fn upload(
+    req: HttpRequest,
+    mut payload: Multipart,
+) -> Result<impl Responder, UploadError> {
+    let max_size = config().limits.max_upload_size;
+
+    // reject early if the client tells us the body is too large. the multipart body is a bit
+    // larger than the file itself, but that's close enough
+    let content_length = req
+        .headers()
+        .get(actix_web::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_size) {
+        return Err(UploadError::TooLarge(max_size));
+    }
+
     let mut job: Option<Job> = None;
     while let Some(item) = payload.next().await {
         let mut field = item?;
@@ -64,60 +265,36 @@ pub async fn upload(mut payload: Multipart) -> Result<impl Responder, UploadErro
         // get file name
         let filename = content_disposition
             .get_filename()
-            .ok_or_else(|| UploadError::NoFilename)?;
-
-        let ext = filename
-            .split('.')
-            .last()
-            .and_then(|ext| {
-                Some(
-                    ext.chars()
-                        .filter(|c| c.is_alphanumeric())
-                        .collect::<String>(),
-                )
-            })
-            .ok_or_else(|| UploadError::NoExtension)?;
-
-        if let Err(e) = ext.parse::<ConverterFormat>() {
-            log::error!("failed to parse file extension: {}", e);
-            return Err(UploadError::InvalidExtension(ext));
-        }
+            .ok_or_else(|| UploadError::NoFilename)?
+            .to_string();
 
-        info!("uploaded file: {}", filename);
+        let hint = extension_hint(&filename);
 
-        let mut bytes = Vec::new();
-        while let Some(chunk) = field.next().await {
-            let data = chunk?;
-            bytes.extend_from_slice(&data);
-        }
         let rand: [u8; 64] = rand::random();
         let token = hex::encode(rand);
-        let our_job = Job::new(token, ext.to_string());
-        job = Some(our_job.clone());
-        let mut app_state = APP_STATE.lock().await;
-        // fs::write(format!("input/{}.{}", our_job.id, ext), &bytes).await?;
-        let mut file = File::create(format!("input/{}.{}", our_job.id, ext)).await?;
-        file.write_all(&bytes).await?;
-        file.flush().await?;
+        let mut our_job = Job::new(token, String::new());
+        our_job.filename = Some(filename.clone());
+
+        // write to a temporary file first so a half-written upload is never picked up as input
+        let tmp_path = our_job.part_path();
+        let mut size = 0;
+        let mut file = File::create(&tmp_path).await?;
+        if let Err(e) = write_stream(&mut field, &mut file, &mut size, max_size).await {
+            drop(file);
+            fs::remove_file(&tmp_path).await.ok();
+            return Err(e);
+        }
         drop(file);
-        app_state.jobs.insert(our_job.id, our_job.clone());
-        // spawn a new task which waits an hour before removing the job
-        tokio::spawn(async move {
-            tokio::time::sleep(crate::INPUT_LIFETIME).await;
-            info!(
-                "{:?} elapsed, removing {}",
-                crate::INPUT_LIFETIME,
-                our_job.id
-            );
-            let mut app_state = APP_STATE.lock().await;
-            app_state.jobs.remove(&our_job.id);
-            fs::remove_file(format!("input/{}.{}", our_job.id, ext))
-                .await
-                .ok();
-        });
+        let our_job = finish_upload(our_job, &tmp_path, hint.as_deref()).await?;
+
+        info!(
+            "uploaded file: {} ({} bytes, detected as {})",
+            filename, size, our_job.from
+        );
+
+        job = Some(our_job);
         break;
     }
-    let mut job = job.ok_or_else(|| UploadError::NoFile)?;
-    job.total_frames().await?;
+    let job = job.ok_or_else(|| UploadError::NoFile)?;
     Ok(ApiResponse::Success(job))
 }
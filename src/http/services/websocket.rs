@@ -1,23 +1,13 @@
-use std::{collections::BTreeMap, io::ErrorKind};
-
 use actix_web::{get, rt, web, Error, HttpRequest, HttpResponse};
 use actix_ws::AggregatedMessage;
-use discord_webhook2::{message, webhook::DiscordWebhook};
 use futures_util::StreamExt as _;
-use log::error;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
+use tokio::sync::broadcast::{self, error::RecvError};
 use uuid::Uuid;
 
 use crate::{
-    converter::{
-        format::ConverterFormat,
-        job::{JobState, ProgressUpdate},
-        speed::ConversionSpeed,
-        Converter,
-    },
-    state::APP_STATE,
-    OUTPUT_LIFETIME,
+    converter::{job::ProgressUpdate, options::ConversionOptions, speed::ConversionSpeed},
+    runner::{self, JobEvent, StartOptions, Subscription},
 };
 
 fn default_keep_metadata() -> bool {
@@ -35,17 +25,30 @@ pub enum Message {
         speed: ConversionSpeed,
         #[serde(default = "default_keep_metadata")]
         keep_metadata: bool,
+        // boxed, it's most of the message's size
+        #[serde(flatten)]
+        options: Box<ConversionOptions>,
+        #[serde(default)]
+        callback_url: Option<String>,
     },
 
     #[serde(rename = "cancelJob", rename_all = "camelCase")]
     CancelJob { token: String, job_id: Uuid },
 
+    // follow a job started elsewhere, e.g. before the page was reloaded
+    #[serde(rename = "subscribe", rename_all = "camelCase")]
+    Subscribe { token: String, job_id: Uuid },
+
     #[serde(rename = "jobFinished", rename_all = "camelCase")]
     JobFinished { job_id: Uuid },
 
     #[serde(rename = "jobCancelled", rename_all = "camelCase")]
     JobCancelled { job_id: Uuid },
 
+    // sent while waiting for a free encoder, position is 1-based
+    #[serde(rename = "queued", rename_all = "camelCase")]
+    Queued { job_id: Uuid, position: usize },
+
     #[serde(rename = "progressUpdate", rename_all = "camelCase")]
     ProgressUpdate(ProgressUpdate),
 
@@ -59,6 +62,28 @@ impl From<Message> for String {
     }
 }
 
+impl Message {
+    fn from_event(job_id: Uuid, event: JobEvent) -> Self {
+        match event {
+            JobEvent::Queued(position) => Message::Queued { job_id, position },
+            JobEvent::Progress(progress) => Message::ProgressUpdate(progress),
+            JobEvent::Finished => Message::JobFinished { job_id },
+            JobEvent::Cancelled => Message::JobCancelled { job_id },
+            JobEvent::Failed(message) => Message::Error { message },
+        }
+    }
+}
+
+// the job this socket is currently following, if any
+type Following = Option<(Uuid, broadcast::Receiver<JobEvent>)>;
+
+async fn next_event(following: &mut Following) -> (Uuid, Result<JobEvent, RecvError>) {
+    match following {
+        Some((job_id, events)) => (*job_id, events.recv().await),
+        None => std::future::pending().await,
+    }
+}
+
 #[get("/ws")]
 pub async fn websocket(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
     let (res, mut session, stream) = actix_ws::handle(&req, stream)?;
@@ -67,313 +92,135 @@ pub async fn websocket(req: HttpRequest, stream: web::Payload) -> Result<HttpRes
         .aggregate_continuations()
         .max_continuation_size(2_usize.pow(20));
 
+    // jobs run on their own, so closing the socket only stops the updates, not the conversion
     rt::spawn(async move {
-        while let Some(Ok(AggregatedMessage::Text(text))) = stream.next().await {
-            let message: Message = match serde_json::from_str(&text) {
-                Ok(message) => message,
-                Err(e) => {
-                    let message: String = Message::Error {
-                        message: format!("failed to parse message: {}", e),
+        let mut following: Following = None;
+
+        'socket: loop {
+            let text = tokio::select! {
+                new_message = stream.next() => {
+                    match new_message {
+                        Some(Ok(AggregatedMessage::Text(text))) => text,
+                        Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
                     }
-                    .into();
-                    session.text(message).await.unwrap();
-                    continue;
                 }
-            };
 
-            if let Message::StartJob {
-                token,
-                job_id,
-                to,
-                speed,
-                keep_metadata,
-            } = message
-            {
-                let Some(mut job) = ({
-                    let mut app_state = APP_STATE.lock().await;
-                    let job = app_state.jobs.get_mut(&job_id);
-                    let clone = job.as_ref().map(|j| (*j).clone());
-                    if let Some(job) = job {
-                        if job.completed() {
-                            let message: String = Message::Error {
-                                message: "job already completed".to_string(),
+                (job_id, event) = next_event(&mut following) => {
+                    match event {
+                        Ok(event) => {
+                            if event.is_final() {
+                                following = None;
+                            }
+                            let message: String = Message::from_event(job_id, event).into();
+                            if session.text(message).await.is_err() {
+                                break;
                             }
-                            .into();
-                            session.text(message).await.unwrap();
-                            continue;
                         }
-                        job.to = Some(to.clone());
-                    }
-                    clone
-                }) else {
-                    let message: String = Message::Error {
-                        message: "job not found".to_string(),
-                    }
-                    .into();
-                    session.text(message).await.unwrap();
-                    continue;
-                };
-
-                if job.auth != token {
-                    let message: String = Message::Error {
-                        message: "invalid token".to_string(),
+                        // we only missed some progress updates, the next ones will do
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => following = None,
                     }
-                    .into();
-                    session.text(message).await.unwrap();
                     continue;
                 }
+            };
 
-                let Ok(from) = job.from.parse::<ConverterFormat>() else {
+            let message: Message = match serde_json::from_str(&text) {
+                Ok(message) => message,
+                Err(e) => {
                     let message: String = Message::Error {
-                        message: "invalid input format".to_string(),
+                        message: format!("failed to parse message: {}", e),
                     }
                     .into();
-                    session.text(message).await.unwrap();
-                    continue;
-                };
-
-                let Ok(to) = to.parse::<ConverterFormat>() else {
-                    let message: String = Message::Error {
-                        message: "invalid output format".to_string(),
+                    if session.text(message).await.is_err() {
+                        break;
                     }
-                    .into();
-                    session.text(message).await.unwrap();
                     continue;
-                };
-
-                let converter = Converter::new(from, to, speed, keep_metadata);
-
-                let (gpu, vaapi_device_path) = {
-                    let app_state = APP_STATE.lock().await;
-                    let gpu = app_state
-                        .gpu
-                        .ok_or_else(|| "GPU not initialized, please restart vertd.".to_string());
-                    let device_path = app_state.vaapi_device_path.clone();
-                    (gpu, device_path)
-                };
-
-                let gpu = match gpu {
-                    Ok(gpu) => gpu,
-                    Err(msg) => {
-                        let message: String = Message::Error { message: msg }.into();
-                        session.text(message).await.unwrap();
-                        continue;
-                    }
-                };
-
-                let (mut rx, process) = match converter
-                    .convert(&mut job, &gpu, vaapi_device_path.as_deref())
-                    .await
-                {
-                    Ok((rx, process)) => (rx, process),
-                    Err(e) => {
-                        let message: String = Message::Error {
-                            message: format!("failed to convert: {}", e),
-                        }
-                        .into();
-                        session.text(message).await.unwrap();
-                        continue;
-                    }
-                };
-
-                // store process in case user wants to cancel
-                {
-                    let mut app_state = APP_STATE.lock().await;
-                    app_state.active_processes.insert(job_id, process);
                 }
+            };
 
-                let mut logs = Vec::new();
-                let mut job_cancelled = false;
+            let result = match message {
+                Message::StartJob {
+                    token,
+                    job_id,
+                    to,
+                    speed,
+                    keep_metadata,
+                    options,
+                    callback_url,
+                } => {
+                    let options = StartOptions {
+                        to,
+                        speed,
+                        keep_metadata,
+                        options: *options,
+                        callback_url,
+                    };
+                    runner::start(job_id, &token, options)
+                        .await
+                        .map(|events| following = Some((job_id, events)))
+                }
 
-                // send progress updates and listen for cancellation
-                loop {
-                    tokio::select! {
-                        update = rx.recv() => {
-                            match update {
-                                Some(ProgressUpdate::Error(err)) => {
-                                    logs.push(err);
-                                }
-                                Some(progress) => {
-                                    let message: String = Message::ProgressUpdate(progress).into();
-                                    session.text(message).await.unwrap();
-                                }
-                                None => {
-                                    // conversion finished
-                                    break;
+                Message::Subscribe { token, job_id } => {
+                    match runner::subscribe(job_id, &token).await {
+                        Ok(Subscription::Live(progress, events)) => {
+                            // catch up on what happened before we got here
+                            for event in progress.events() {
+                                let message: String = Message::from_event(job_id, event).into();
+                                if session.text(message).await.is_err() {
+                                    break 'socket;
                                 }
                             }
+                            following = Some((job_id, events));
+                            Ok(())
                         }
-
-                        new_message = stream.next() => {
-                            if let Some(Ok(AggregatedMessage::Text(text))) = new_message {
-                                if let Ok(parsed_message) = serde_json::from_str::<Message>(&text) {
-                                    if let Message::CancelJob { token: cancel_token, job_id: cancel_job_id } = parsed_message {
-                                        if cancel_job_id == job_id && cancel_token == token {
-                                            log::info!("cancelling job {}", job_id);
-
-                                            let mut app_state = APP_STATE.lock().await;
-                                            if let Some(mut process) = app_state.active_processes.remove(&job_id) {
-                                                if let Err(e) = process.kill().await {
-                                                    log::error!("failed to kill process for job {}: {}", job_id, e);
-                                                } else {
-                                                    log::info!("killed process for job {}", job_id);
-                                                    job_cancelled = true;
-                                                }
-                                            }
-
-                                            if let Some(job) = app_state.jobs.get_mut(&job_id) {
-                                                job.state = JobState::Completed;
-                                            }
-                                            drop(app_state);
-
-                                            let message: String = Message::JobCancelled { job_id }.into();
-                                            session.text(message).await.unwrap();
-
-                                            break;
-                                        } else {
-                                            let message: String = Message::Error {
-                                                message: "invalid token or job id for cancellation".to_string(),
-                                            }
-                                            .into();
-                                            session.text(message).await.unwrap();
-                                        }
-                                    }
-                                }
-                            } else if new_message.is_none() {
-                                // ws closed
+                        Ok(Subscription::Finished) => {
+                            let message: String = Message::JobFinished { job_id }.into();
+                            if session.text(message).await.is_err() {
                                 break;
                             }
+                            Ok(())
                         }
+                        Ok(Subscription::Failed(message)) => {
+                            let message: String = Message::Error { message }.into();
+                            if session.text(message).await.is_err() {
+                                break;
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
                     }
                 }
 
-                {
-                    let mut app_state = APP_STATE.lock().await;
-                    if let Some(job) = app_state.jobs.get_mut(&job_id) {
-                        job.state = JobState::Completed;
-                    }
-
-                    if !job_cancelled {
-                        // clean process only if not cancelled
-                        app_state.active_processes.remove(&job_id);
-                    } else {
-                        // clean up job if cancelled
-                        app_state.jobs.remove(&job_id);
-                        drop(app_state);
-
-                        if let Err(e) =
-                            fs::remove_file(&format!("input/{}.{}", job.id, job.from)).await
-                        {
-                            if e.kind() != ErrorKind::NotFound {
-                                log::error!(
-                                    "failed to remove input file after cancellation: {}",
-                                    e
-                                );
+                Message::CancelJob { token, job_id } => {
+                    match runner::cancel(job_id, &token).await {
+                        // sockets following the job get JobCancelled through its events
+                        Ok(()) if following.as_ref().is_some_and(|(id, _)| *id == job_id) => Ok(()),
+                        Ok(()) => {
+                            let message: String = Message::JobCancelled { job_id }.into();
+                            if session.text(message).await.is_err() {
+                                break;
                             }
+                            Ok(())
                         }
-                        continue;
+                        Err(e) => Err(e),
                     }
-
-                    drop(app_state);
                 }
 
-                // check if output/{}.{} exists and isn't empty
-                let is_empty = fs::metadata(&format!("output/{}.{}", job_id, to))
-                    .await
-                    .map(|m| m.len() == 0)
-                    .unwrap_or(true);
-
-                if is_empty {
-                    // hacky :/
-                    let mut app_state = APP_STATE.lock().await;
-                    if let Some(job) = app_state.jobs.get_mut(&job_id) {
-                        job.state = JobState::Failed;
-                    }
-                    drop(app_state);
-                    log::error!("job {} failed", job_id);
-
-                    let error_message = if logs.is_empty() {
-                        "No error logs.".to_string()
-                    } else {
-                        logs.join("\n")
-                    };
-
-                    let message: String = Message::Error {
-                        message: error_message,
-                    }
-                    .into();
-                    session.text(message).await.unwrap();
-
-                    let from = job.from.clone();
-                    let to = to.to_string().to_string();
+                _ => Ok(()),
+            };
 
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_job_failure(job_id, from, to, logs.join("\n")).await
-                        {
-                            log::error!("failed to handle job failure: {}", e);
-                        }
-                    });
-                } else {
-                    let message: String = Message::JobFinished { job_id }.into();
-                    session.text(message).await.unwrap();
+            if let Err(e) = result {
+                let message: String = Message::Error {
+                    message: e.to_string(),
+                }
+                .into();
+                if session.text(message).await.is_err() {
+                    break;
                 }
-
-                tokio::spawn(async move {
-                    // wait 15 seconds to let the user decide if they want to keep the file,
-                    // and also for the copy op to finish...
-                    tokio::time::sleep(tokio::time::Duration::from_secs(15)).await;
-                    match fs::remove_file(&format!("input/{}.{}", job.id, job.from)).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("failed to remove input file: {}", e);
-                        }
-                    };
-                });
-
-                tokio::spawn(async move {
-                    tokio::time::sleep(OUTPUT_LIFETIME).await;
-                    let mut app_state = APP_STATE.lock().await;
-                    app_state.jobs.remove(&job_id);
-                    drop(app_state);
-
-                    let path = format!("output/{}.{}", job_id, to.to_string());
-                    if let Err(e) = fs::remove_file(&path).await {
-                        if e.kind() != ErrorKind::NotFound {
-                            log::error!("failed to remove output file: {}", e);
-                        }
-                    }
-                });
             }
         }
     });
 
     Ok(res)
 }
-
-async fn handle_job_failure(
-    job_id: Uuid,
-    from: String,
-    to: String,
-    logs: String,
-) -> anyhow::Result<()> {
-    let client_url = std::env::var("WEBHOOK_URL")?;
-    let mentions = std::env::var("WEBHOOK_PINGS").unwrap_or_else(|_| "".to_string());
-
-    let mut files = BTreeMap::new();
-    files.insert(format!("{}.log", job_id), logs.as_bytes().to_vec());
-
-    let client = DiscordWebhook::new(&client_url)?;
-    let message = message::Message::new(|m| {
-        m.content(format!("🚨🚨🚨 {}", mentions)).embed(|e| {
-            e.title("vertd job failed!")
-                .field(|f| f.name("job id").value(job_id))
-                .field(|f| f.name("from").value(format!(".{}", from)).inline(true))
-                .field(|f| f.name("to").value(format!(".{}", to)).inline(true))
-                .color(0xff83fa)
-        })
-    });
-
-    client.send_with_files(&message, files).await?;
-
-    Ok(())
-}
@@ -0,0 +1,194 @@
+// keeps input/, output/ and permanent/ from filling the disk. each directory can have a maximum
+// size and a maximum age, the oldest files go first. files in input/ and output/ belong to jobs,
+// so ones no job knows about are removed too. kept files in permanent/ outlive their jobs and are
+// only ever trimmed by the limits
+
+use std::{collections::HashSet, io::ErrorKind, time::Duration, time::SystemTime};
+
+use log::{error, info};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::{config, DiskLimits},
+    http,
+    kept::sidecar_path,
+    state::APP_STATE,
+};
+
+// files younger than this are never orphans, they might still be on their way into a job
+const ORPHAN_GRACE: Duration = Duration::from_secs(10 * 60);
+
+struct Dir {
+    path: &'static str,
+    limits: &'static DiskLimits,
+    // whether every file in here should have a job
+    owned_by_jobs: bool,
+}
+
+fn dirs() -> [Dir; 3] {
+    let config = config();
+    [
+        Dir {
+            path: &config.directories.input,
+            limits: &config.limits.input,
+            owned_by_jobs: true,
+        },
+        Dir {
+            path: &config.directories.output,
+            limits: &config.limits.output,
+            owned_by_jobs: true,
+        },
+        Dir {
+            path: &config.directories.permanent,
+            limits: &config.limits.permanent,
+            owned_by_jobs: false,
+        },
+    ]
+}
+
+struct Entry {
+    path: String,
+    size: u64,
+    modified: SystemTime,
+    job_id: Option<Uuid>,
+}
+
+async fn scan(dir: &str) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let name = entry.file_name().to_string_lossy().to_string();
+        // sidecars of kept files go together with the file they describe
+        if name.ends_with(".json") {
+            continue;
+        }
+
+        // every file we write is named {job id}.{extension}
+        let job_id = name
+            .split_once('.')
+            .map_or(name.as_str(), |(stem, _)| stem)
+            .parse()
+            .ok();
+
+        entries.push(Entry {
+            path: format!("{}/{}", dir, name),
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            job_id,
+        });
+    }
+    Ok(entries)
+}
+
+async fn remove(entry: &Entry, reason: &str) -> bool {
+    http::forget_sent(&entry.path);
+    match fs::remove_file(&entry.path).await {
+        Ok(()) => {
+            fs::remove_file(sidecar_path(&entry.path)).await.ok();
+            info!(
+                "removed {} ({} bytes, {}) from disk",
+                entry.path, entry.size, reason
+            );
+            true
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => true,
+        Err(e) => {
+            error!("failed to remove {}: {}", entry.path, e);
+            false
+        }
+    }
+}
+
+async fn enforce(dir: &Dir) {
+    let limits = dir.limits;
+    let mut entries = match scan(dir.path).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("failed to scan {}/: {}", dir.path, e);
+            return;
+        }
+    };
+    entries.sort_by_key(|entry| entry.modified);
+
+    // files of running jobs and unfinished uploads are in use, so they're never touched.
+    // everything else we remove takes its job with it, a job without its files is useless
+    let (busy, known) = {
+        let app_state = APP_STATE.lock().await;
+        let busy = app_state
+            .running
+            .keys()
+            .chain(app_state.uploads.keys())
+            .copied()
+            .collect::<HashSet<_>>();
+        let known = app_state.jobs.ids().into_iter().collect::<HashSet<_>>();
+        (busy, known)
+    };
+    entries.retain(|entry| entry.job_id.is_none_or(|id| !busy.contains(&id)));
+
+    let now = SystemTime::now();
+    let age = |entry: &Entry| now.duration_since(entry.modified).unwrap_or_default();
+    let mut total = entries.iter().map(|entry| entry.size).sum::<u64>();
+    let mut dropped_jobs = HashSet::new();
+
+    for entry in &entries {
+        let reason = if dir.owned_by_jobs
+            && age(entry) > ORPHAN_GRACE
+            && entry.job_id.is_none_or(|id| !known.contains(&id))
+        {
+            "no job"
+        } else if limits.max_age.is_some_and(|max_age| age(entry) > max_age) {
+            "max age"
+        } else if limits.max_size.is_some_and(|max_size| total > max_size) {
+            "max size"
+        } else {
+            continue;
+        };
+
+        if remove(entry, reason).await {
+            total -= entry.size;
+            if let Some(id) = entry
+                .job_id
+                .filter(|id| dir.owned_by_jobs && known.contains(id))
+            {
+                dropped_jobs.insert(id);
+            }
+        }
+    }
+
+    if dropped_jobs.is_empty() {
+        return;
+    }
+
+    let mut app_state = APP_STATE.lock().await;
+    let jobs = dropped_jobs
+        .iter()
+        // it might have been started again while we were busy
+        .filter(|id| !app_state.running.contains_key(id))
+        .copied()
+        .collect::<Vec<_>>();
+    let jobs = jobs
+        .iter()
+        .filter_map(|id| app_state.jobs.remove(id))
+        .collect::<Vec<_>>();
+    drop(app_state);
+
+    for job in jobs {
+        info!("removed job {} along with its files", job.id);
+        let paths = [Some(job.input_path()), job.output_path()];
+        for path in paths.into_iter().flatten() {
+            fs::remove_file(&path).await.ok();
+        }
+    }
+}
+
+pub async fn sweep() {
+    for dir in dirs() {
+        enforce(&dir).await;
+    }
+}
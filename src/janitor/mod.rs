@@ -0,0 +1,106 @@
+// one background task that cleans up after everything else: expired jobs and their files,
+// abandoned resumable uploads, files that were scheduled for removal and, less often, whatever
+// disk.rs finds on disk
+
+mod disk;
+
+use std::{io::ErrorKind, sync::Mutex, time::Duration};
+
+use lazy_static::lazy_static;
+use log::info;
+use tokio::fs;
+
+use crate::{converter::job::unix_now, http, state::APP_STATE};
+
+// how often the janitor looks around
+const INTERVAL: Duration = Duration::from_secs(5);
+// how often it goes through the directories on disk, which is a lot slower
+const DISK_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // files which aren't tied to a job, with the unix timestamp they should be removed at
+    static ref SCHEDULED: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+}
+
+// removes `path` once `delay` has passed
+pub fn remove_later(path: String, delay: Duration) {
+    let mut scheduled = SCHEDULED.lock().unwrap();
+    scheduled.push((path, unix_now() + delay.as_secs()));
+}
+
+async fn remove_files(paths: impl IntoIterator<Item = String>) {
+    for path in paths {
+        http::forget_sent(&path);
+        if let Err(e) = fs::remove_file(&path).await {
+            if e.kind() != ErrorKind::NotFound {
+                log::error!("failed to remove {}: {}", path, e);
+            }
+        }
+    }
+}
+
+async fn sweep() {
+    let mut app_state = APP_STATE.lock().await;
+
+    // running jobs are left alone, even if they sat in the queue for longer than their lifetime
+    let expired = app_state
+        .jobs
+        .expired(unix_now())
+        .into_iter()
+        .filter(|job| !app_state.running.contains_key(&job.id))
+        .collect::<Vec<_>>();
+    for job in &expired {
+        app_state.jobs.remove(&job.id);
+    }
+
+    let abandoned = app_state
+        .uploads
+        .iter()
+        .filter(|(_, upload)| upload.job.expired() && !upload.writing)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    let abandoned = abandoned
+        .into_iter()
+        .filter_map(|id| app_state.uploads.remove(&id))
+        .collect::<Vec<_>>();
+    drop(app_state);
+
+    for job in expired {
+        info!("job {} expired, removing it", job.id);
+        remove_files(
+            [Some(job.input_path()), job.output_path()]
+                .into_iter()
+                .flatten(),
+        )
+        .await;
+    }
+
+    for upload in abandoned {
+        info!("removing abandoned upload {}", upload.job.id);
+        remove_files([upload.job.part_path()]).await;
+    }
+
+    let now = unix_now();
+    let due = {
+        let mut scheduled = SCHEDULED.lock().unwrap();
+        let (due, later) = scheduled
+            .drain(..)
+            .partition::<Vec<_>, _>(|(_, at)| *at <= now);
+        *scheduled = later;
+        due
+    };
+    remove_files(due.into_iter().map(|(path, _)| path)).await;
+}
+
+pub fn start() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(INTERVAL);
+        let mut disk_interval = tokio::time::interval(DISK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => sweep().await,
+                _ = disk_interval.tick() => disk::sweep().await,
+            }
+        }
+    });
+}
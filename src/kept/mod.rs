@@ -0,0 +1,136 @@
+// files users chose to keep after a failed conversion, so they can be looked into later. each
+// one is permanent/{job id}.{format}, usually with a {file}.json sidecar describing the failure
+
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::config,
+    converter::job::{unix_now, ConversionDetails, Job},
+    state::APP_STATE,
+};
+
+// written to "{kept file}.json" so a failure can be reproduced without the job or notifications
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeptFileInfo {
+    pub job_id: Uuid,
+    pub from: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // unix timestamp (seconds)
+    pub kept_at: u64,
+    pub vertd_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<ConversionDetails>,
+    // ffmpeg's stderr, or whatever else made the conversion fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+}
+
+impl KeptFileInfo {
+    pub fn new(job: &Job) -> Self {
+        Self {
+            job_id: job.id,
+            from: job.from.clone(),
+            filename: job.filename.clone(),
+            kept_at: unix_now(),
+            vertd_version: env!("CARGO_PKG_VERSION").to_string(),
+            conversion: job.conversion.clone(),
+            logs: job.error.clone(),
+        }
+    }
+}
+
+pub fn sidecar_path(kept_path: &str) -> String {
+    format!("{}.json", kept_path)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeptFile {
+    pub id: Uuid,
+    pub size: u64,
+    // the format the file was uploaded as, i.e. its extension
+    pub format: String,
+    // unix timestamp (seconds) of when it was kept
+    pub kept_at: u64,
+    // what it was called when it was uploaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    // how the failed conversion was run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<ConversionDetails>,
+    // why the conversion failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+    #[serde(skip)]
+    pub path: String,
+}
+
+pub async fn list() -> std::io::Result<Vec<KeptFile>> {
+    let dir = &config().directories.permanent;
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // kept files are always {job id}.{format}, anything else (like sidecars) isn't one
+        let Some((id, format)) = name.split_once('.') else {
+            continue;
+        };
+        if format.contains('.') {
+            continue;
+        }
+        let Ok(id) = id.parse() else {
+            continue;
+        };
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+
+        files.push(KeptFile {
+            id,
+            size: metadata.len(),
+            format: format.to_string(),
+            kept_at: metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            filename: None,
+            conversion: None,
+            logs: None,
+            path: format!("{}/{}", dir, name),
+        });
+    }
+
+    for file in &mut files {
+        let info = fs::read(sidecar_path(&file.path))
+            .await
+            .ok()
+            .and_then(|info| serde_json::from_slice::<KeptFileInfo>(&info).ok());
+        if let Some(info) = info {
+            file.filename = info.filename;
+            file.conversion = info.conversion;
+            file.logs = info.logs;
+        }
+    }
+
+    // files kept before sidecars existed only have their job to go on, while it's still around
+    let app_state = APP_STATE.lock().await;
+    for file in files.iter_mut().filter(|file| file.logs.is_none()) {
+        file.logs = app_state.jobs.get(&file.id).and_then(|job| job.error);
+    }
+    drop(app_state);
+
+    files.sort_by_key(|file| std::cmp::Reverse(file.kept_at));
+    Ok(files)
+}
+
+pub async fn find(id: Uuid) -> std::io::Result<Option<KeptFile>> {
+    Ok(list().await?.into_iter().find(|file| file.id == id))
+}
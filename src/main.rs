@@ -1,9 +1,19 @@
+mod callback;
+mod cli;
+mod config;
 mod converter;
 mod http;
+mod janitor;
+mod kept;
+mod notify;
+mod runner;
+mod scheduler;
 mod state;
+mod store;
 
-use std::{env, process::exit, time::Duration};
+use std::{path::Path, process::exit};
 
+use cli::{Cli, Command};
 use converter::gpu::{get_gpu, ConverterGPU};
 use dotenv::dotenv;
 use env_logger::Env;
@@ -11,9 +21,6 @@ use http::start_http;
 use log::{error, info, warn};
 use tokio::fs;
 
-pub const INPUT_LIFETIME: Duration = Duration::from_secs(60 * 60);
-pub const OUTPUT_LIFETIME: Duration = Duration::from_secs(60 * 60);
-
 enum FFUtil {
     FFmpeg,
     FFprobe,
@@ -39,96 +46,75 @@ async fn ffutil_version(util: FFUtil) -> anyhow::Result<String> {
     Ok(version.to_string())
 }
 
-fn parse_gpu(gpu_str: &str) -> anyhow::Result<ConverterGPU> {
-    match gpu_str.to_lowercase().as_str() {
-        "amd" => Ok(ConverterGPU::AMD),
-        "intel" => Ok(ConverterGPU::Intel),
-        "nvidia" => Ok(ConverterGPU::NVIDIA),
-        "apple" => Ok(ConverterGPU::Apple),
-        "cpu" => Ok(ConverterGPU::CPU),
-        _ => Err(anyhow::anyhow!(
-            "{}. Valid options: amd, intel, nvidia, apple, cpu",
-            gpu_str
-        )),
+// vertd only ever puts files directly in its directories, so there's nothing to recurse into.
+// removing just the files also means a misconfigured directory can't take anything else with it
+async fn remove_files_in(dir: &str, matches: impl Fn(&Path) -> bool) {
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let is_file = entry.file_type().await.is_ok_and(|t| t.is_file());
+        if is_file && matches(&entry.path()) {
+            fs::remove_file(entry.path()).await.ok();
+        }
     }
 }
 
-fn get_forced_gpu() -> Option<ConverterGPU> {
-    // cli argument (-gpu <value>)
-    let args: Vec<String> = env::args().collect();
-    if let Some(gpu_arg_pos) = args.iter().position(|arg| arg == "-gpu" || arg == "--gpu") {
-        if let Some(gpu_value) = args.get(gpu_arg_pos + 1) {
-            match parse_gpu(gpu_value) {
-                Ok(gpu) => {
-                    info!("using GPU from command line argument: {}", gpu);
-                    return Some(gpu);
-                }
-                Err(e) => {
-                    warn!("invalid GPU specified in command line argument: {}", e);
-                }
-            }
-        } else {
-            warn!("GPU argument specified but no value provided");
-        }
-    }
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    let cli = Cli::parse_args();
 
-    // environment variable
-    if let Ok(gpu_env) = env::var("VERTD_FORCE_GPU") {
-        match parse_gpu(&gpu_env) {
-            Ok(gpu) => {
-                info!(
-                    "using GPU from environment variable VERTD_FORCE_GPU: {}",
-                    gpu
-                );
-                return Some(gpu);
-            }
-            Err(e) => {
-                warn!(
-                    "invalid GPU specified in VERTD_FORCE_GPU environment variable: {}",
-                    e
-                );
+    // subcommands print what matters themselves, RUST_LOG still works for digging deeper
+    let default_filter = if cli.command.is_some() {
+        "off"
+    } else {
+        "vertd"
+    };
+    env_logger::Builder::from_env(Env::default().default_filter_or(default_filter)).init();
+
+    let config_file = config::load(&cli)?;
+    let config = config::config();
+
+    if let Some(command) = cli.command {
+        let succeeded = match command {
+            Command::Convert {
+                input,
+                to,
+                speed,
+                options,
+                output,
+                strip_metadata,
+            } => {
+                let gpu = match config.gpu.force {
+                    Some(gpu) => gpu,
+                    None => get_gpu().await.unwrap_or(ConverterGPU::CPU),
+                };
+                let options = cli::convert::ConvertOptions {
+                    input,
+                    to,
+                    speed,
+                    conversion: *options,
+                    output,
+                    keep_metadata: !strip_metadata,
+                };
+                cli::convert::run(options, gpu).await?
             }
+            Command::Replay { id } => cli::replay::run(id, config.gpu.force).await?,
+        };
+        if !succeeded {
+            exit(1);
         }
+        return Ok(());
     }
 
-    None
-}
-
-fn get_vaapi_device_path() -> Option<String> {
-    // cli argument (-vaapi-device <value>)
-    let args: Vec<String> = env::args().collect();
-    if let Some(vaapi_arg_pos) = args
-        .iter()
-        .position(|arg| arg == "-vaapi-device" || arg == "--vaapi-device")
-    {
-        if let Some(device_value) = args.get(vaapi_arg_pos + 1) {
-            info!(
-                "using VA-API device path from command line argument: {}",
-                device_value
-            );
-            return Some(device_value.clone());
-        } else {
-            warn!("VA-API device path argument specified but no value provided");
-        }
-    }
-
-    // environment variable
-    if let Ok(device_path) = env::var("VERTD_VAAPI_DEVICE_PATH") {
-        info!(
-            "using VA-API device path from environment variable VERTD_VAAPI_DEVICE_PATH: {}",
-            device_path
-        );
-        return Some(device_path);
+    info!("starting vertd");
+    match &config_file {
+        Some(path) => info!("read config from {}", path.display()),
+        None => info!("no config file, using environment variables and defaults"),
     }
+    info!("effective config:\n{}", config);
 
-    None
-}
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    dotenv().ok();
-    env_logger::Builder::from_env(Env::default().default_filter_or("vertd")).init();
-    info!("starting vertd");
     let ffmpeg_version = match ffutil_version(FFUtil::FFmpeg).await {
         Ok(version) => version,
         Err(e) => {
@@ -150,15 +136,15 @@ async fn main() -> anyhow::Result<()> {
         ffmpeg_version, ffprobe_version
     );
 
-    // check if env var or cli arg is specified for gpu, if not fallback to auto-detection
-    let gpu = match get_forced_gpu() {
-        Some(forced_gpu) => Ok(forced_gpu),
+    // a forced gpu (from the command line, env or config file) skips auto-detection
+    let gpu = match config.gpu.force {
+        Some(forced_gpu) => {
+            info!("using forced GPU: {}", forced_gpu);
+            Ok(forced_gpu)
+        }
         None => get_gpu().await,
     };
 
-    // get VA-API device path from CLI or env var
-    let vaapi_device_path = get_vaapi_device_path();
-
     match &gpu {
         Ok(gpu) => {
             if matches!(gpu, ConverterGPU::CPU) {
@@ -178,9 +164,10 @@ async fn main() -> anyhow::Result<()> {
 
             #[cfg(target_os = "linux")]
             if matches!(gpu, ConverterGPU::AMD | ConverterGPU::Intel) {
-                let device_path = vaapi_device_path
-                    .as_ref()
-                    .map(|s| s.as_str())
+                let device_path = config
+                    .gpu
+                    .vaapi_device
+                    .as_deref()
                     .unwrap_or("/dev/dri/renderD128");
                 info!("using VA-API device path: {}", device_path);
             }
@@ -193,26 +180,53 @@ async fn main() -> anyhow::Result<()> {
 
     // default to CPU if detection failed
     let gpu = gpu.unwrap_or(ConverterGPU::CPU);
+    let mut job_store = match store::open(&config.jobs) {
+        Ok(job_store) => job_store,
+        Err(e) => {
+            error!("failed to open job store: {}", e);
+            exit(1);
+        }
+    };
+
+    let directories = &config.directories;
+    for dir in [
+        &directories.input,
+        &directories.output,
+        &directories.permanent,
+    ] {
+        fs::create_dir_all(dir).await?;
+    }
+
+    if job_store.persistent() {
+        // keep whatever files the stored jobs still point to, everything else gets cleaned up
+        // by rehydrate() or by the janitor. resumable uploads aren't persisted, so their partial
+        // files are useless after a restart
+        remove_files_in(&directories.input, |path| {
+            path.extension().is_some_and(|ext| ext == "part")
+        })
+        .await;
+    } else {
+        // nothing from the last run is coming back, so neither are its files
+        remove_files_in(&directories.input, |_| true).await;
+        remove_files_in(&directories.output, |_| true).await;
+    }
+
+    store::rehydrate(job_store.as_mut()).await;
+
     {
         let mut app_state = state::APP_STATE.lock().await;
         app_state.gpu = Some(gpu);
-        app_state.vaapi_device_path = vaapi_device_path;
+        app_state.jobs = job_store;
     }
 
-    // remove input/ and output/ recursively if they exist -- we don't care if this fails tho
-    let _ = fs::remove_dir_all("input").await;
-    let _ = fs::remove_dir_all("output").await;
+    // expired jobs, abandoned uploads and leftover inputs are cleaned up from here on
+    janitor::start();
 
-    // create input/ and output/ directories
-    fs::create_dir("input").await?;
-    fs::create_dir("output").await?;
-
-    // also a permanent/ directory for kept files
-    match fs::create_dir("permanent").await {
-        Ok(_) => {}
-        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
-        Err(e) => return Err(e.into()),
-    }
+    notify::init()?;
+    notify::notify(notify::NotifyEvent::ServerStarted {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        gpu: gpu.to_string(),
+    });
 
     start_http().await?;
     Ok(())
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use discord_webhook2::{message::Message, webhook::DiscordWebhook};
+
+use super::{kept_download_note, Notifier, NotifyEvent};
+
+const COLOR: u32 = 0xff83fa;
+
+pub struct DiscordNotifier {
+    url: String,
+    // formatted into the message, e.g. "<@&role_id> <@user_id>"
+    pings: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(url: String, pings: String) -> Self {
+        Self { url, pings }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn notify(&self, event: &NotifyEvent) -> anyhow::Result<()> {
+        let client = DiscordWebhook::new(&self.url)?;
+        let pings = &self.pings;
+
+        match event {
+            NotifyEvent::JobFailed {
+                job_id,
+                from,
+                to,
+                logs,
+            } => {
+                let mut files = BTreeMap::new();
+                files.insert(format!("{}.log", job_id), logs.as_bytes().to_vec());
+
+                let message = Message::new(|m| {
+                    m.content(format!("🚨🚨🚨 {}", pings)).embed(|e| {
+                        e.title(event.title())
+                            .field(|f| f.name("job id").value(*job_id))
+                            .field(|f| f.name("from").value(format!(".{}", from)).inline(true))
+                            .field(|f| f.name("to").value(format!(".{}", to)).inline(true))
+                            .color(COLOR)
+                    })
+                });
+
+                client.send_with_files(&message, files).await?;
+            }
+            NotifyEvent::FileKept { url, .. } => {
+                let description = match url {
+                    Some(url) => kept_download_note(&format!("[here]({url})")),
+                    None => event.description(),
+                };
+
+                let message = Message::new(|m| {
+                    m.content(format!("🚨🚨🚨 {}", pings))
+                        .embed(|e| e.title(event.title()).description(description).color(COLOR))
+                });
+
+                client.send(&message).await?;
+            }
+            // nothing's wrong, so no pings
+            NotifyEvent::ServerStarted { .. } => {
+                let message = Message::new(|m| {
+                    m.embed(|e| {
+                        e.title(event.title())
+                            .description(event.description())
+                            .color(COLOR)
+                    })
+                });
+
+                client.send(&message).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+// operator notifications. every notifier that is configured gets every event, see .env.example
+
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use log::{error, info};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::config;
+
+mod discord;
+mod slack;
+mod smtp;
+mod webhook;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum NotifyEvent {
+    #[serde(rename_all = "camelCase")]
+    JobFailed {
+        job_id: Uuid,
+        from: String,
+        to: String,
+        logs: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    FileKept {
+        job_id: Uuid,
+        from: String,
+        // only there if PUBLIC_URL is set
+        url: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ServerStarted { version: String, gpu: String },
+}
+
+impl NotifyEvent {
+    pub fn title(&self) -> &'static str {
+        match self {
+            NotifyEvent::JobFailed { .. } => "vertd job failed!",
+            NotifyEvent::FileKept { .. } => "a file has been kept permanently!",
+            NotifyEvent::ServerStarted { .. } => "vertd started",
+        }
+    }
+
+    // plain text version, for notifiers that can't do anything fancier. logs aren't included
+    pub fn description(&self) -> String {
+        match self {
+            NotifyEvent::JobFailed {
+                job_id, from, to, ..
+            } => {
+                format!("job {job_id} failed to convert .{from} to .{to}")
+            }
+            NotifyEvent::FileKept { url: Some(url), .. } => {
+                kept_download_note(&format!("here: {url}"))
+            }
+            NotifyEvent::FileKept { job_id, from, .. } => {
+                let dir = &config().directories.permanent;
+                format!("it's stored as {dir}/{job_id}.{from}")
+            }
+            NotifyEvent::ServerStarted { version, gpu } => {
+                format!("vertd {version} is up and converting on {gpu}")
+            }
+        }
+    }
+
+    pub fn logs(&self) -> Option<&str> {
+        match self {
+            NotifyEvent::JobFailed { logs, .. } => Some(logs),
+            _ => None,
+        }
+    }
+}
+
+// how to get at a kept file, `link` is however the notifier shows the link
+pub fn kept_download_note(link: &str) -> String {
+    format!("download it {link} (needs the admin token as a bearer token). it stays around until someone deletes it through the admin api.")
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn notify(&self, event: &NotifyEvent) -> anyhow::Result<()>;
+}
+
+static NOTIFIERS: OnceLock<Vec<Box<dyn Notifier>>> = OnceLock::new();
+
+// sets up every notifier that is configured. until then (and in the cli) notify() does nothing
+pub fn init() -> anyhow::Result<()> {
+    let config = &config().notify;
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+
+    if let Some(url) = &config.discord_url {
+        let pings = config.discord_pings.clone().unwrap_or_default();
+        notifiers.push(Box::new(discord::DiscordNotifier::new(
+            url.expose().to_string(),
+            pings,
+        )));
+    }
+
+    if let Some(url) = &config.webhook_url {
+        notifiers.push(Box::new(webhook::WebhookNotifier::new(
+            url.expose().to_string(),
+        )));
+    }
+
+    if let Some(url) = &config.slack_url {
+        notifiers.push(Box::new(slack::SlackNotifier::new(
+            url.expose().to_string(),
+        )));
+    }
+
+    if let Some(url) = &config.smtp_url {
+        let from = config.smtp_from.as_deref().unwrap_or_default();
+        let to = config.smtp_to.as_deref().unwrap_or_default();
+        let notifier =
+            smtp::SmtpNotifier::new(url.expose(), from, to).context("invalid smtp settings")?;
+        notifiers.push(Box::new(notifier));
+    }
+
+    if !notifiers.is_empty() {
+        let names = notifiers.iter().map(|n| n.name()).collect::<Vec<_>>();
+        info!("sending notifications via {}", names.join(", "));
+    }
+
+    if NOTIFIERS.set(notifiers).is_err() {
+        anyhow::bail!("notifiers were already set up");
+    }
+    Ok(())
+}
+
+// fire and forget, a notifier failing only gets logged
+pub fn notify(event: NotifyEvent) {
+    let event = Arc::new(event);
+    for notifier in NOTIFIERS.get().into_iter().flatten() {
+        let event = Arc::clone(&event);
+        tokio::spawn(async move {
+            if let Err(e) = notifier.notify(&event).await {
+                error!("failed to send {} notification: {}", notifier.name(), e);
+            }
+        });
+    }
+}
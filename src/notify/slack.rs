@@ -0,0 +1,48 @@
+// slack incoming webhooks, which mattermost, rocket.chat and friends also understand
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{Notifier, NotifyEvent};
+
+// slack only cuts messages off at 40k characters, but a wall of logs is unreadable long before
+// that. the last 3000 characters are kept, the end is usually the interesting part
+const MAX_LOG_CHARS: usize = 3000;
+
+pub struct SlackNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    async fn notify(&self, event: &NotifyEvent) -> anyhow::Result<()> {
+        let mut text = format!("*{}*\n{}", event.title(), event.description());
+        if let Some(logs) = event.logs().filter(|logs| !logs.is_empty()) {
+            let skip = logs.chars().count().saturating_sub(MAX_LOG_CHARS);
+            let tail = logs.chars().skip(skip).collect::<String>();
+            text.push_str(&format!("\n```{}```", tail));
+        }
+
+        self.client
+            .post(&self.url)
+            .json(&json!({ "text": text }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+// posts the event as json, e.g. {"type":"jobFailed","data":{"jobId":"...",...}}
+
+use async_trait::async_trait;
+
+use super::{Notifier, NotifyEvent};
+
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, event: &NotifyEvent) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .json(event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
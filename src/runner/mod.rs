@@ -0,0 +1,543 @@
+// conversions run in their own task, independently of whoever started them. progress is fanned
+// out over a broadcast channel so any number of clients can follow (or re-attach to) a job
+
+use std::{
+    io::ErrorKind,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use serde::Serialize;
+use tokio::{
+    fs,
+    sync::{broadcast, Notify},
+};
+use uuid::Uuid;
+
+use crate::{
+    callback::{self, CallbackPayload, CallbackState},
+    config::config,
+    converter::{
+        format::ConverterFormat,
+        gpu::ConverterGPU,
+        job::{Job, JobState, ProgressUpdate},
+        options::ConversionOptions,
+        speed::ConversionSpeed,
+        Converter,
+    },
+    janitor,
+    notify::{notify, NotifyEvent},
+    scheduler::{Ticket, SCHEDULER},
+    state::APP_STATE,
+};
+
+// progress updates are small and frequent, subscribers that fall further behind just skip some
+const EVENT_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug)]
+pub enum JobEvent {
+    // 1-based place in the encoder queue
+    Queued(usize),
+    Progress(ProgressUpdate),
+    Finished,
+    Cancelled,
+    Failed(String),
+}
+
+impl JobEvent {
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            JobEvent::Finished | JobEvent::Cancelled | JobEvent::Failed(_)
+        )
+    }
+}
+
+// the latest known progress of a running job, so late subscribers don't start from nothing
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    // place in the encoder queue, 0 once the conversion has started
+    pub position: usize,
+    pub frame: Option<u64>,
+    pub fps: Option<f64>,
+    pub percent: Option<f64>,
+    pub eta: Option<f64>,
+    pub output_size: Option<u64>,
+    pub speed: Option<f64>,
+}
+
+impl JobProgress {
+    fn apply(&mut self, event: &JobEvent) {
+        match event {
+            JobEvent::Queued(position) => self.position = *position,
+            JobEvent::Progress(update) => {
+                self.position = 0;
+                match update {
+                    ProgressUpdate::Frame(frame) => self.frame = Some(*frame),
+                    ProgressUpdate::FPS(fps) => self.fps = Some(*fps),
+                    ProgressUpdate::Percent(percent) => self.percent = Some(*percent),
+                    ProgressUpdate::Eta(eta) => self.eta = Some(*eta),
+                    ProgressUpdate::OutputSize(size) => self.output_size = Some(*size),
+                    ProgressUpdate::Speed(speed) => self.speed = Some(*speed),
+                    ProgressUpdate::Error(_) => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // the snapshot replayed as the events a subscriber would have seen so far
+    pub fn events(&self) -> Vec<JobEvent> {
+        if self.position > 0 {
+            return vec![JobEvent::Queued(self.position)];
+        }
+
+        [
+            self.frame.map(ProgressUpdate::Frame),
+            self.fps.map(ProgressUpdate::FPS),
+            self.output_size.map(ProgressUpdate::OutputSize),
+            self.speed.map(ProgressUpdate::Speed),
+            self.percent.map(ProgressUpdate::Percent),
+            self.eta.map(ProgressUpdate::Eta),
+        ]
+        .into_iter()
+        .flatten()
+        .map(JobEvent::Progress)
+        .collect()
+    }
+}
+
+pub struct RunningJob {
+    pub progress: JobProgress,
+    events: broadcast::Sender<JobEvent>,
+    cancel: Arc<Notify>,
+}
+
+pub enum Subscription {
+    Live(JobProgress, broadcast::Receiver<JobEvent>),
+    Finished,
+    Failed(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunError {
+    #[error("job not found")]
+    JobNotFound,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("job already completed")]
+    AlreadyCompleted,
+    #[error("job is already running")]
+    AlreadyRunning,
+    #[error("job is not running")]
+    NotRunning,
+    #[error("invalid input format")]
+    InvalidInputFormat,
+    #[error("invalid output format")]
+    InvalidOutputFormat,
+    #[error("invalid callback url, it has to be an http(s) url with a public host")]
+    InvalidCallbackUrl,
+    #[error("callback urls aren't enabled on this server")]
+    CallbackUrlsDisabled,
+    #[error("{0}")]
+    InvalidOptions(String),
+    #[error("GPU not initialized, please restart vertd.")]
+    GpuNotInitialized,
+}
+
+// everything a client gets to pick when starting a conversion
+pub struct StartOptions {
+    pub to: String,
+    pub speed: ConversionSpeed,
+    pub keep_metadata: bool,
+    pub options: ConversionOptions,
+    // where to POST the result once the job ends, on top of VERTD_CALLBACK_URL. only allowed
+    // with VERTD_CALLBACK_ALLOW_CLIENT_URLS
+    pub callback_url: Option<String>,
+}
+
+// validates the request and spawns the conversion. the returned receiver is subscribed before
+// anything runs, so the caller doesn't miss any events
+pub async fn start(
+    job_id: Uuid,
+    token: &str,
+    options: StartOptions,
+) -> Result<broadcast::Receiver<JobEvent>, RunError> {
+    let mut app_state = APP_STATE.lock().await;
+    let job = app_state.jobs.get(&job_id).ok_or(RunError::JobNotFound)?;
+
+    if job.completed() {
+        return Err(RunError::AlreadyCompleted);
+    }
+
+    if job.auth != token {
+        return Err(RunError::InvalidToken);
+    }
+
+    if app_state.running.contains_key(&job_id) {
+        return Err(RunError::AlreadyRunning);
+    }
+
+    let from = job
+        .from
+        .parse::<ConverterFormat>()
+        .map_err(|_| RunError::InvalidInputFormat)?;
+    let to = options
+        .to
+        .parse::<ConverterFormat>()
+        .map_err(|_| RunError::InvalidOutputFormat)?;
+    options
+        .options
+        .check(to)
+        .map_err(RunError::InvalidOptions)?;
+    if let Some(url) = &options.callback_url {
+        if !config().callback.allow_client_urls {
+            return Err(RunError::CallbackUrlsDisabled);
+        }
+        if !callback::valid_client_url(url) {
+            return Err(RunError::InvalidCallbackUrl);
+        }
+    }
+    let gpu = app_state.gpu.ok_or(RunError::GpuNotInitialized)?;
+    let vaapi_device_path = config().gpu.vaapi_device.clone();
+
+    // failed jobs can be retried, so start from a clean slate
+    let Some(job) = app_state.jobs.update(&job_id, |job| {
+        job.to = Some(to.to_string());
+        job.state = JobState::Processing;
+        job.error = None;
+    }) else {
+        return Err(RunError::JobNotFound);
+    };
+
+    // queue up right away so the caller's status already shows the right position
+    let ticket = SCHEDULER.enqueue(gpu, job_id);
+    let (events, rx) = broadcast::channel(EVENT_CAPACITY);
+    let cancel = Arc::new(Notify::new());
+    app_state.running.insert(
+        job_id,
+        RunningJob {
+            progress: JobProgress {
+                position: ticket.position(),
+                ..Default::default()
+            },
+            events,
+            cancel: Arc::clone(&cancel),
+        },
+    );
+    drop(app_state);
+
+    let converter = Converter::new(from, to, options.speed, options.keep_metadata)
+        .with_options(options.options);
+    tokio::spawn(run(
+        job,
+        ticket,
+        converter,
+        gpu,
+        vaapi_device_path,
+        cancel,
+        options.callback_url,
+    ));
+
+    Ok(rx)
+}
+
+// attaches to a job started elsewhere. jobs which already ended report how they ended instead
+pub async fn subscribe(job_id: Uuid, token: &str) -> Result<Subscription, RunError> {
+    let app_state = APP_STATE.lock().await;
+    let job = app_state.jobs.get(&job_id).ok_or(RunError::JobNotFound)?;
+
+    if job.auth != token {
+        return Err(RunError::InvalidToken);
+    }
+
+    if let Some(running) = app_state.running.get(&job_id) {
+        return Ok(Subscription::Live(
+            running.progress.clone(),
+            running.events.subscribe(),
+        ));
+    }
+
+    match job.state {
+        JobState::Completed => Ok(Subscription::Finished),
+        JobState::Failed => Ok(Subscription::Failed(
+            job.error.unwrap_or_else(|| "job failed".to_string()),
+        )),
+        JobState::Processing => Err(RunError::NotRunning),
+    }
+}
+
+// the job as stored, plus its live progress if it's running right now
+pub async fn status(job_id: Uuid, token: &str) -> Result<(Job, Option<JobProgress>), RunError> {
+    let app_state = APP_STATE.lock().await;
+    let job = app_state.jobs.get(&job_id).ok_or(RunError::JobNotFound)?;
+
+    if job.auth != token {
+        return Err(RunError::InvalidToken);
+    }
+
+    let progress = app_state
+        .running
+        .get(&job_id)
+        .map(|running| running.progress.clone());
+    Ok((job, progress))
+}
+
+// asks the job's task to stop, the Cancelled event follows once it has
+pub async fn cancel(job_id: Uuid, token: &str) -> Result<(), RunError> {
+    let app_state = APP_STATE.lock().await;
+    let job = app_state.jobs.get(&job_id).ok_or(RunError::JobNotFound)?;
+
+    if job.auth != token {
+        return Err(RunError::InvalidToken);
+    }
+
+    let running = app_state.running.get(&job_id).ok_or(RunError::NotRunning)?;
+    running.cancel.notify_one();
+    Ok(())
+}
+
+async fn publish(job_id: Uuid, event: JobEvent) {
+    let mut app_state = APP_STATE.lock().await;
+    if let Some(running) = app_state.running.get_mut(&job_id) {
+        running.progress.apply(&event);
+        // no subscribers is fine, the job keeps going without anyone watching
+        let _ = running.events.send(event);
+    }
+}
+
+// records how the job ended and tells subscribers in one go, so anyone subscribing in between
+// either gets the final event or sees the final state in the store
+async fn finish(job: &Job, state: JobState, event: JobEvent) {
+    let error = match &event {
+        JobEvent::Failed(message) => Some(message.clone()),
+        _ => None,
+    };
+    let conversion = job.conversion.clone();
+
+    let mut app_state = APP_STATE.lock().await;
+    app_state.jobs.update(&job.id, |stored| {
+        stored.state = state;
+        stored.error = error;
+        stored.conversion = conversion;
+        // the output sticks around for the output lifetime from now on
+        stored.extend_lifetime(config().jobs.output_lifetime);
+    });
+    if let Some(running) = app_state.running.remove(&job.id) {
+        let _ = running.events.send(event);
+    }
+}
+
+async fn remove_cancelled(job: &Job) {
+    let mut app_state = APP_STATE.lock().await;
+    app_state.jobs.remove(&job.id);
+    if let Some(running) = app_state.running.remove(&job.id) {
+        let _ = running.events.send(JobEvent::Cancelled);
+    }
+    drop(app_state);
+
+    let paths = [Some(job.input_path()), job.output_path()];
+    for path in paths.into_iter().flatten() {
+        if let Err(e) = fs::remove_file(&path).await {
+            if e.kind() != ErrorKind::NotFound {
+                error!("failed to remove {} after cancellation: {}", path, e);
+            }
+        }
+    }
+}
+
+// how a job ended, for the callback
+struct Outcome {
+    state: CallbackState,
+    logs: Option<String>,
+    // time spent converting, not counting the queue
+    duration: Duration,
+}
+
+async fn run(
+    mut job: Job,
+    ticket: Ticket,
+    converter: Converter,
+    gpu: ConverterGPU,
+    vaapi_device_path: Option<String>,
+    cancel: Arc<Notify>,
+    callback_url: Option<String>,
+) {
+    let to = converter.conversion.to.to_string();
+    let outcome = execute(
+        &mut job,
+        ticket,
+        &converter,
+        gpu,
+        vaapi_device_path,
+        &cancel,
+    )
+    .await;
+
+    let output_size = match outcome.state {
+        CallbackState::Completed => fs::metadata(job.output_path().unwrap_or_default())
+            .await
+            .ok()
+            .map(|m| m.len()),
+        _ => None,
+    };
+
+    callback::send(
+        callback_url.as_deref(),
+        &CallbackPayload {
+            job_id: job.id,
+            state: outcome.state,
+            from: job.from.clone(),
+            to,
+            output_size,
+            duration: outcome.duration.as_secs_f64(),
+            logs: outcome.logs,
+        },
+    );
+}
+
+async fn execute(
+    job: &mut Job,
+    mut ticket: Ticket,
+    converter: &Converter,
+    gpu: ConverterGPU,
+    vaapi_device_path: Option<String>,
+    cancel: &Notify,
+) -> Outcome {
+    let job_id = job.id;
+
+    // wait for a free encoder slot
+    while ticket.position() > 0 {
+        publish(job_id, JobEvent::Queued(ticket.position())).await;
+        tokio::select! {
+            _ = ticket.changed() => {}
+            _ = cancel.notified() => {
+                info!("cancelling queued job {}", job_id);
+                drop(ticket);
+                remove_cancelled(job).await;
+                return Outcome {
+                    state: CallbackState::Cancelled,
+                    logs: None,
+                    duration: Duration::ZERO,
+                };
+            }
+        }
+    }
+
+    let started = Instant::now();
+    let (mut rx, mut process) = match converter
+        .convert(job, &gpu, vaapi_device_path.as_deref())
+        .await
+    {
+        Ok((rx, process)) => (rx, process),
+        Err(e) => {
+            converter.finalize(job, false).await.ok();
+            let message = format!("failed to convert: {}", e);
+            finish(job, JobState::Failed, JobEvent::Failed(message.clone())).await;
+            return Outcome {
+                state: CallbackState::Failed,
+                logs: Some(message),
+                duration: started.elapsed(),
+            };
+        }
+    };
+
+    let mut logs = Vec::new();
+    let cancelled = loop {
+        tokio::select! {
+            update = rx.recv() => {
+                match update {
+                    Some(ProgressUpdate::Error(err)) => logs.push(err),
+                    Some(progress) => publish(job_id, JobEvent::Progress(progress)).await,
+                    // conversion finished
+                    None => break false,
+                }
+            }
+
+            _ = cancel.notified() => {
+                info!("cancelling job {}", job_id);
+                match process.kill().await {
+                    Ok(_) => info!("killed process for job {}", job_id),
+                    Err(e) => error!("failed to kill process for job {}: {}", job_id, e),
+                }
+                break true;
+            }
+        }
+    };
+
+    // stdout closing doesn't mean ffmpeg is done writing the output yet
+    let status = process.wait().await.ok();
+    drop(ticket);
+    let duration = started.elapsed();
+
+    if cancelled {
+        converter.finalize(job, false).await.ok();
+        remove_cancelled(job).await;
+        return Outcome {
+            state: CallbackState::Cancelled,
+            logs: None,
+            duration,
+        };
+    }
+
+    let succeeded = status.is_some_and(|status| status.success());
+    if let Err(e) = converter.finalize(job, succeeded).await {
+        logs.push(e.to_string());
+    }
+
+    // a failed ffmpeg can still leave a partial output behind, so it has to have exited cleanly
+    // as well as written something
+    let to = converter.conversion.to.to_string();
+    let is_empty = fs::metadata(job.output_path().unwrap_or_default())
+        .await
+        .map(|m| m.len() == 0)
+        .unwrap_or(true);
+    if !succeeded && logs.is_empty() {
+        if let Some(status) = status {
+            logs.push(format!("ffmpeg exited with {}", status));
+        }
+    }
+
+    if !succeeded || is_empty {
+        error!("job {} failed", job_id);
+
+        let error_message = if logs.is_empty() {
+            "No error logs.".to_string()
+        } else {
+            logs.join("\n")
+        };
+
+        notify(NotifyEvent::JobFailed {
+            job_id,
+            from: job.from.clone(),
+            to,
+            logs: logs.join("\n"),
+        });
+
+        finish(
+            job,
+            JobState::Failed,
+            JobEvent::Failed(error_message.clone()),
+        )
+        .await;
+        // the input stays until the job expires, so it can still be kept or converted again
+        Outcome {
+            state: CallbackState::Failed,
+            logs: Some(error_message),
+            duration,
+        }
+    } else {
+        finish(job, JobState::Completed, JobEvent::Finished).await;
+
+        // wait 15 seconds for the copy op to finish...
+        janitor::remove_later(job.input_path(), Duration::from_secs(15));
+
+        Outcome {
+            state: CallbackState::Completed,
+            logs: None,
+            duration,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+use log::info;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{config::config, converter::gpu::ConverterGPU};
+
+lazy_static! {
+    pub static ref SCHEDULER: Scheduler = Scheduler::default();
+}
+
+// every GPU gets its own FIFO queue and its own limit on concurrent encodes
+#[derive(Default)]
+pub struct Scheduler {
+    lanes: Mutex<HashMap<ConverterGPU, Lane>>,
+}
+
+struct Lane {
+    limit: usize,
+    running: usize,
+    waiting: VecDeque<(Uuid, watch::Sender<usize>)>,
+}
+
+impl Lane {
+    fn new(gpu: ConverterGPU) -> Self {
+        Self {
+            limit: config().gpu.max_concurrent.get(gpu),
+            running: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    // starts as many waiting jobs as there are free slots and tells everyone else where they are
+    fn promote(&mut self) {
+        while self.running < self.limit {
+            let Some((_, position)) = self.waiting.pop_front() else {
+                break;
+            };
+            self.running += 1;
+            position.send_replace(0);
+        }
+
+        // only wake up the ones that actually moved
+        for (i, (_, position)) in self.waiting.iter().enumerate() {
+            position.send_if_modified(|p| std::mem::replace(p, i + 1) != i + 1);
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn enqueue(&'static self, gpu: ConverterGPU, id: Uuid) -> Ticket {
+        let mut lanes = self.lanes.lock().unwrap();
+        let lane = lanes.entry(gpu).or_insert_with(|| Lane::new(gpu));
+
+        let (tx, rx) = watch::channel(lane.waiting.len() + 1);
+        lane.waiting.push_back((id, tx));
+        lane.promote();
+
+        if *rx.borrow() > 0 {
+            info!(
+                "job {} queued at position {} ({} running on {})",
+                id,
+                *rx.borrow(),
+                lane.running,
+                gpu
+            );
+        }
+
+        Ticket {
+            scheduler: self,
+            gpu,
+            id,
+            position: rx,
+        }
+    }
+}
+
+// a place in the queue, which turns into a running slot once `position()` hits 0.
+// dropping it either leaves the queue or frees the slot for the next job
+pub struct Ticket {
+    scheduler: &'static Scheduler,
+    gpu: ConverterGPU,
+    id: Uuid,
+    position: watch::Receiver<usize>,
+}
+
+impl Ticket {
+    // 0 means the job may run, anything else is its 1-based place in line
+    pub fn position(&self) -> usize {
+        *self.position.borrow()
+    }
+
+    pub async fn changed(&mut self) -> usize {
+        // the sender only goes away once we're running, which is a final state anyway
+        let _ = self.position.changed().await;
+        self.position()
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut lanes = self.scheduler.lanes.lock().unwrap();
+        let Some(lane) = lanes.get_mut(&self.gpu) else {
+            return;
+        };
+
+        if let Some(index) = lane.waiting.iter().position(|(id, _)| *id == self.id) {
+            lane.waiting.remove(index);
+        } else {
+            lane.running = lane.running.saturating_sub(1);
+        }
+        lane.promote();
+    }
+}
@@ -4,22 +4,34 @@ use lazy_static::lazy_static;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
-use crate::converter::{gpu::ConverterGPU, job::Job};
+use crate::{
+    converter::{gpu::ConverterGPU, job::Job},
+    runner::RunningJob,
+    store::{JobStore, MemoryJobStore},
+};
+
+// a resumable upload which hasn't been finalized into a job yet
+pub struct PendingUpload {
+    pub job: Job,
+    pub size: u64,
+    pub offset: u64,
+    pub writing: bool,
+}
 
 pub struct AppState {
-    pub jobs: HashMap<Uuid, Job>,
-    pub active_processes: HashMap<Uuid, tokio::process::Child>,
+    pub jobs: Box<dyn JobStore>,
+    pub uploads: HashMap<Uuid, PendingUpload>,
+    pub running: HashMap<Uuid, RunningJob>,
     pub gpu: Option<ConverterGPU>,
-    pub vaapi_device_path: Option<String>,
 }
 
 impl AppState {
     pub fn default() -> Self {
         Self {
-            jobs: HashMap::new(),
-            active_processes: HashMap::new(),
+            jobs: Box::new(MemoryJobStore::default()),
+            uploads: HashMap::new(),
+            running: HashMap::new(),
             gpu: None,
-            vaapi_device_path: None,
         }
     }
 }
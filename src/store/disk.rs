@@ -0,0 +1,171 @@
+use std::collections::{BTreeSet, HashMap};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::JobStore;
+use crate::converter::{job::Job, probe::MediaInfo};
+
+// what's actually stored: the job and its probe, which serializing the job itself leaves out
+#[derive(Serialize)]
+struct StoredJobRef<'a> {
+    #[serde(flatten)]
+    job: &'a Job,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<&'a MediaInfo>,
+}
+
+#[derive(Deserialize)]
+struct StoredJob {
+    #[serde(flatten)]
+    job: Job,
+    #[serde(default)]
+    info: Option<MediaInfo>,
+}
+
+pub struct SledJobStore {
+    db: sled::Db,
+    // when each job expires, kept in memory so the janitor doesn't have to decode every job
+    // it has stored to find the few that are due
+    expiry: BTreeSet<(u64, Uuid)>,
+    expires_at: HashMap<Uuid, u64>,
+}
+
+impl SledJobStore {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut store = Self {
+            db: sled::open(path)?,
+            expiry: BTreeSet::new(),
+            expires_at: HashMap::new(),
+        };
+        for job in store.all() {
+            store.index(&job);
+        }
+        Ok(store)
+    }
+
+    fn index(&mut self, job: &Job) {
+        self.unindex(&job.id);
+        self.expiry.insert((job.expires_at, job.id));
+        self.expires_at.insert(job.id, job.expires_at);
+    }
+
+    fn unindex(&mut self, id: &Uuid) {
+        if let Some(expires_at) = self.expires_at.remove(id) {
+            self.expiry.remove(&(expires_at, *id));
+        }
+    }
+
+    // the store is used under the app state lock, so the flush happens in the background instead
+    // of blocking everyone else while it hits the disk
+    fn flush(&self) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            if let Err(e) = db.flush_async().await {
+                error!("failed to flush the job store: {}", e);
+            }
+        });
+    }
+
+    fn decode(id: &[u8], bytes: &[u8]) -> Option<Job> {
+        match serde_json::from_slice::<StoredJob>(bytes) {
+            Ok(StoredJob { mut job, info }) => {
+                if let Some(info) = info {
+                    job.set_media_info(info);
+                }
+                Some(job)
+            }
+            Err(e) => {
+                error!("failed to decode stored job {}: {}", hex::encode(id), e);
+                None
+            }
+        }
+    }
+}
+
+impl JobStore for SledJobStore {
+    fn get(&self, id: &Uuid) -> Option<Job> {
+        match self.db.get(id.as_bytes()) {
+            Ok(bytes) => bytes.and_then(|b| Self::decode(id.as_bytes(), &b)),
+            Err(e) => {
+                error!("failed to read job {} from the job store: {}", id, e);
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, job: Job) {
+        let stored = StoredJobRef {
+            job: &job,
+            info: job.cached_media_info(),
+        };
+        let bytes = match serde_json::to_vec(&stored) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("failed to encode job {}: {}", job.id, e);
+                return;
+            }
+        };
+
+        match self.db.insert(job.id.as_bytes(), bytes) {
+            Ok(_) => {
+                self.index(&job);
+                self.flush();
+            }
+            Err(e) => error!("failed to write job {} to the job store: {}", job.id, e),
+        }
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Option<Job> {
+        let result = self.db.remove(id.as_bytes());
+        self.unindex(id);
+        self.flush();
+        match result {
+            Ok(bytes) => bytes.and_then(|b| Self::decode(id.as_bytes(), &b)),
+            Err(e) => {
+                error!("failed to remove job {} from the job store: {}", id, e);
+                None
+            }
+        }
+    }
+
+    fn all(&self) -> Vec<Job> {
+        self.db
+            .iter()
+            .filter_map(|entry| match entry {
+                Ok((id, bytes)) => Self::decode(&id, &bytes),
+                Err(e) => {
+                    error!("failed to read from the job store: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // keys only, nothing gets decoded
+    fn ids(&self) -> Vec<Uuid> {
+        self.db
+            .iter()
+            .keys()
+            .filter_map(|key| match key {
+                Ok(key) => Uuid::from_slice(&key).ok(),
+                Err(e) => {
+                    error!("failed to read from the job store: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn expired(&self, now: u64) -> Vec<Job> {
+        self.expiry
+            .range(..=(now, Uuid::max()))
+            .filter_map(|(_, id)| self.get(id))
+            .collect()
+    }
+
+    fn persistent(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use log::{info, warn};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::{JobsConfig, StoreKind},
+    converter::job::{Job, JobState},
+};
+
+pub mod disk;
+
+pub use disk::SledJobStore;
+
+// where jobs live. the in-memory store forgets everything on restart, the on-disk one doesn't
+pub trait JobStore: Send + Sync {
+    fn get(&self, id: &Uuid) -> Option<Job>;
+    fn insert(&mut self, job: Job);
+    fn remove(&mut self, id: &Uuid) -> Option<Job>;
+    fn all(&self) -> Vec<Job>;
+    // like all(), but without loading the jobs themselves
+    fn ids(&self) -> Vec<Uuid>;
+    // jobs that expired at or before `now`
+    fn expired(&self, now: u64) -> Vec<Job>;
+
+    // whether jobs (and their files) survive a restart
+    fn persistent(&self) -> bool;
+}
+
+impl dyn JobStore {
+    // read-modify-write helper, since not every store can hand out a `&mut Job`
+    pub fn update(&mut self, id: &Uuid, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut job = self.get(id)?;
+        f(&mut job);
+        self.insert(job.clone());
+        Some(job)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryJobStore {
+    jobs: HashMap<Uuid, Job>,
+}
+
+impl JobStore for MemoryJobStore {
+    fn get(&self, id: &Uuid) -> Option<Job> {
+        self.jobs.get(id).cloned()
+    }
+
+    fn insert(&mut self, job: Job) {
+        self.jobs.insert(job.id, job);
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Option<Job> {
+        self.jobs.remove(id)
+    }
+
+    fn all(&self) -> Vec<Job> {
+        self.jobs.values().cloned().collect()
+    }
+
+    fn ids(&self) -> Vec<Uuid> {
+        self.jobs.keys().copied().collect()
+    }
+
+    fn expired(&self, now: u64) -> Vec<Job> {
+        self.jobs
+            .values()
+            .filter(|job| job.expires_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    fn persistent(&self) -> bool {
+        false
+    }
+}
+
+pub fn open(config: &JobsConfig) -> anyhow::Result<Box<dyn JobStore>> {
+    match config.store {
+        StoreKind::Memory => Ok(Box::new(MemoryJobStore::default())),
+        StoreKind::Sled => {
+            info!("using on-disk job store at {}", config.store_path);
+            Ok(Box::new(SledJobStore::open(&config.store_path)?))
+        }
+    }
+}
+
+async fn exists(path: &str) -> bool {
+    fs::metadata(path).await.is_ok()
+}
+
+// goes through the jobs left over from the last run: expired ones and ones whose files are gone
+// get dropped, conversions that were interrupted by the restart are marked as failed
+pub async fn rehydrate(store: &mut dyn JobStore) {
+    let mut restored = 0;
+
+    for mut job in store.all() {
+        let input = job.input_path();
+        let output = job.output_path();
+        let has_input = exists(&input).await;
+        let has_output = match &output {
+            Some(output) => exists(output).await,
+            None => false,
+        };
+
+        if job.expired() || (!has_input && !has_output) {
+            store.remove(&job.id);
+            fs::remove_file(&input).await.ok();
+            if let Some(output) = &output {
+                fs::remove_file(output).await.ok();
+            }
+            continue;
+        }
+
+        // the ffmpeg process died with the old instance
+        if job.state == JobState::Processing && job.to.is_some() {
+            warn!(
+                "job {} was interrupted by a restart, marking as failed",
+                job.id
+            );
+            job.state = JobState::Failed;
+            job.error = Some("the conversion was interrupted by a restart".to_string());
+            store.insert(job);
+        }
+
+        restored += 1;
+    }
+
+    info!("restored {} job(s) from the job store", restored);
+}